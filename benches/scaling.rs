@@ -0,0 +1,25 @@
+/*!
+Shows how the time `postfix_macros!` needs to rewrite
+its input scales with the size of the input.
+
+The measurements are taken when the benchmark gets
+compiled, as that's when the proc macro runs.
+Run it with `cargo bench --features bench`, and with
+`cargo bench --features bench,full-parse` to
+measure the full parser.
+*/
+
+use postfix_macros_impl::__postfix_macros_bench;
+
+fn main() {
+	let timings :&[(&str, usize, u128)] = &__postfix_macros_bench!();
+	if timings.is_empty() {
+		println!("No measurements, build with optimizations to take them.");
+		return;
+	}
+	println!("{:<12} {:>8} {:>10} {:>12}", "input", "size", "total ms", "ns per unit");
+	for (kind, size, nanos) in timings {
+		println!("{:<12} {:>8} {:>10.2} {:>12.1}", kind, size,
+			*nanos as f64 / 1e6, *nanos as f64 / *size as f64);
+	}
+}
@@ -0,0 +1,304 @@
+/*!
+Receiver detection using syn's full Rust expression grammar.
+
+Only compiled with the `full-parse` feature.
+*/
+
+use proc_macro::{Delimiter, TokenStream, TokenTree as Tt};
+use proc_macro2::{TokenStream as TokenStream2, Ident, Span};
+use quote::{quote, ToTokens};
+use syn::{Block, Expr, Stmt};
+use syn::parse::Parser;
+use crate::AngleBrackets;
+
+/// Name of the method call that the postfix macro
+/// invocation is replaced with before parsing.
+const MARKER :&str = "__postfix_macros_receiver";
+
+/// Name of the ident that the previous rewritten
+/// invocation is replaced with before parsing.
+const PLACEHOLDER :&str = "__postfix_macros_invocation";
+
+/// The tt's before a postfix macro invocation
+#[derive(Clone, Copy)]
+struct Tokens<'a> {
+	tts :&'a [Tt],
+	/// The matching of the angle brackets in `tts`
+	angles :&'a AngleBrackets,
+	/// The range of the rewritten previous invocation.
+	/// syn gets a placeholder ident instead of it, as
+	/// the receivers of a chain contain each other, and
+	/// parsing them over and over would take quadratic time.
+	prev :Option<(usize, usize)>,
+}
+
+impl Tokens<'_> {
+	/// Whether the tt at `pos` is a `>` that closes a `<`
+	fn closes_angle(&self, pos :usize) -> bool {
+		matches!(&self.tts[pos], Tt::Punct(p) if p.as_char() == '>') &&
+			self.angles.opening(pos).is_some()
+	}
+	/// The range of the previous invocation,
+	/// if it's inside the tt's from `from` on
+	fn prev_from(&self, from :usize) -> Option<(usize, usize)> {
+		self.prev.filter(|&(start, _)| start >= from)
+	}
+	/// Converts the number of tt's at the end of the
+	/// tt's from `from` on, as syn sees them with the
+	/// placeholder, to the number of tt's in `tts`.
+	fn original_len(&self, from :usize, len :usize) -> usize {
+		match self.prev_from(from) {
+			Some((start, end)) => {
+				let placeholder = start - from;
+				let parsed_len = self.tts.len() - from - (end - start) + 1;
+				if len <= parsed_len && parsed_len - len <= placeholder {
+					len + end - start - 1
+				} else {
+					len
+				}
+			},
+			None => len,
+		}
+	}
+	/// Whether the tt's from `from` to `end` contain a `$`,
+	/// not counting the previous invocation, which syn
+	/// doesn't get to see.
+	fn contains_dollar(&self, from :usize, end :usize) -> bool {
+		match self.prev {
+			Some((start, prev_end)) if start < end && prev_end > from => {
+				contains_dollar(&self.tts[from..start.max(from)])
+					|| contains_dollar(&self.tts[prev_end.min(end)..end])
+			},
+			_ => contains_dollar(&self.tts[from..end]),
+		}
+	}
+}
+
+/// Determines the number of tt's at the end of `tts`
+/// that form the receiver of a postfix macro invocation.
+///
+/// `angles` is the matching of the angle brackets in
+/// `tts`, and `prev` is the range of the rewritten
+/// previous invocation in `tts`, if there is one.
+///
+/// Returns `None` if syn can't parse the tokens, e.g.
+/// because they contain `macro_rules!` metavariables.
+pub(crate) fn receiver_length(tts :&[Tt], angles :&AngleBrackets,
+		prev :Option<(usize, usize)>) -> Option<usize> {
+	let tokens = Tokens { tts, angles, prev };
+	// A postfix macro invocation binds tighter than
+	// anything that can follow it, so the tokens after
+	// the invocation don't matter for its receiver.
+	// Look at the operand after the last binary operator
+	// first, and then at the expression after the last
+	// separator, like inside function arguments or match
+	// arms. Trying every suffix instead would make long
+	// sums, argument lists or matches take quadratic time.
+	let len = match operand_receiver_length(tokens)
+			.or_else(|| separator_receiver_length(tokens)) {
+		Some(len) => len,
+		None => {
+			// Everything before the last `;` belongs to
+			// an earlier statement.
+			let start = tts.iter()
+				.rposition(|tt| matches!(tt, Tt::Punct(p) if p.as_char() == ';'))
+				.map(|pos| pos + 1)
+				.unwrap_or(0);
+			// syn can't parse macro_rules metavariables.
+			// Bail out instead of finding a shorter receiver
+			// that doesn't contain them.
+			if tokens.contains_dollar(start, tts.len()) {
+				return None;
+			}
+			// Parse the tokens as statements first, so that
+			// e.g. `match x {} (y).foo!()` gets the receiver `(y)`
+			// just like it would in rustc.
+			statements_receiver_length(tokens, start)
+				.or_else(|| expression_receiver_length(tokens, start))?
+		},
+	};
+
+	// We count the tt's of syn's output, which is not always
+	// equal to the input, e.g. for `a.0.1`. Make sure that the
+	// tt's we found form the receiver on their own.
+	if len > tts.len() || expression_receiver_length(tokens, tts.len() - len) != Some(len) {
+		return None;
+	}
+	Some(len)
+}
+
+/// Finds the receiver in the operand after the last binary
+/// operator of the current expression, like in `a + b.foo!()`.
+///
+/// Long sums or other operator chains inside a single
+/// statement would take quadratic time otherwise.
+/// The operator must not be separated from the invocation
+/// by a block, as in `if a + b {}.foo!()`, the operator
+/// is part of the receiver.
+fn operand_receiver_length(tokens :Tokens<'_>) -> Option<usize> {
+	let tts = tokens.tts;
+	let op = (0..tts.len()).rev()
+		.take_while(|&i| match &tts[i] {
+			Tt::Punct(p) => p.as_char() != ';' && !is_separator(tts, i),
+			Tt::Group(gr) => gr.delimiter() != Delimiter::Brace,
+			_ => true,
+		})
+		.find(|&i| matches!(&tts[i], Tt::Punct(p) if "+-*/%^&|=".contains(p.as_char())))?;
+	if tokens.contains_dollar(op + 1, tts.len()) {
+		return None;
+	}
+	let len = expression_receiver_length(tokens, op + 1)?;
+	// The operator might be inside generic arguments, like
+	// the `+` in `f::<dyn A + B>(x).foo!()`, making the
+	// suffix `B>(x)` a comparison with the receiver `(x)`.
+	let before = tts.len() - len - 1;
+	if before > op && tokens.closes_angle(before) {
+		return None;
+	}
+	Some(len)
+}
+
+/// Finds the receiver in the expression after the last
+/// `,`, `=>` or `:` separator of the current statement.
+///
+/// A `,` can also be part of the generic arguments
+/// or closure parameters before the receiver, in which
+/// case the separator before it is tried.
+fn separator_receiver_length(tokens :Tokens<'_>) -> Option<usize> {
+	let tts = tokens.tts;
+	let mut end = tts.len();
+	loop {
+		let sep = (0..end).rev()
+			.take_while(|&i| !matches!(&tts[i], Tt::Punct(p) if p.as_char() == ';'))
+			.find(|&i| is_separator(tts, i))?;
+		if tokens.contains_dollar(sep + 1, end) {
+			return None;
+		}
+		if let Some(len) = expression_receiver_length(tokens, sep + 1) {
+			// In e.g. `f::<A, B>(x).foo!()`, the suffix
+			// `B>(x)` is a comparison with the receiver `(x)`.
+			// A `>` that doesn't close a `<` is a comparison,
+			// like in `[a > b.foo!(), ..]`.
+			let before = tts.len() - len - 1;
+			if before <= sep || !tokens.closes_angle(before) {
+				return Some(len);
+			}
+		}
+		end = sep;
+	}
+}
+
+/// Whether the tt at `i` separates expressions,
+/// like the `,` of arguments or the `=>` of match arms.
+fn is_separator(tts :&[Tt], i :usize) -> bool {
+	let punct = |i :usize| match tts.get(i) {
+		Some(Tt::Punct(p)) => Some(p.as_char()),
+		_ => None,
+	};
+	match punct(i) {
+		Some(',') => true,
+		Some('>') => i > 0 && punct(i - 1) == Some('='),
+		// Not part of a `::` path separator or a label
+		Some(':') => (i == 0 || punct(i - 1) != Some(':')) && punct(i + 1) != Some(':')
+			&& (i < 2 || punct(i - 2) != Some('\'')),
+		_ => false,
+	}
+}
+
+/// Finds the receiver in the statements formed
+/// by the tt's from `from` on.
+fn statements_receiver_length(tokens :Tokens<'_>, from :usize) -> Option<usize> {
+	let stmts = Block::parse_within.parse2(with_marker(tokens, from, true)).ok()?;
+	let expr = match stmts.last()? {
+		Stmt::Local(local) => match &local.init {
+			Some(init) if init.diverge.is_none() => &init.expr,
+			_ => return None,
+		},
+		Stmt::Expr(expr, _) => expr,
+		_ => return None,
+	};
+	let len = find_receiver(expr).map(token_count)?;
+	Some(tokens.original_len(from, len))
+}
+
+/// Finds the receiver in the expression formed
+/// by the tt's from `from` on.
+fn expression_receiver_length(tokens :Tokens<'_>, from :usize) -> Option<usize> {
+	let expr = syn::parse2::<Expr>(with_marker(tokens, from, false)).ok()?;
+	let len = find_receiver(&expr).map(token_count)?;
+	Some(tokens.original_len(from, len))
+}
+
+/// Appends the marker method call to the tt's from
+/// `from` on, optionally followed by a `;`.
+fn with_marker(tokens :Tokens<'_>, from :usize, semi :bool) -> TokenStream2 {
+	let tts = tokens.tts;
+	let stream = match tokens.prev_from(from) {
+		Some((start, end)) => {
+			let placeholder = proc_macro::Ident::new(PLACEHOLDER,
+				proc_macro::Span::call_site());
+			tts[from..start].iter().cloned()
+				.chain(std::iter::once(Tt::Ident(placeholder)))
+				.chain(tts[end..].iter().cloned())
+				.collect::<TokenStream>()
+		},
+		None => tts[from..].iter().cloned().collect::<TokenStream>(),
+	};
+	let mut stream = TokenStream2::from(stream);
+	let marker = Ident::new(MARKER, Span::call_site());
+	stream.extend(quote!(.#marker()));
+	if semi {
+		stream.extend(quote!(;));
+	}
+	stream
+}
+
+/// Finds the receiver of the marker method call.
+///
+/// As the marker is at the end of the tokens, it must be
+/// on the path formed by the last operand of each expression.
+fn find_receiver(mut expr :&Expr) -> Option<&Expr> {
+	loop {
+		if let Expr::MethodCall(call) = expr {
+			if call.method == MARKER {
+				return Some(&call.receiver);
+			}
+		}
+		expr = match expr {
+			Expr::Assign(e) => &e.right,
+			Expr::Binary(e) => &e.right,
+			Expr::Break(e) => e.expr.as_deref()?,
+			Expr::Closure(e) => &e.body,
+			Expr::Let(e) => &e.expr,
+			Expr::Range(e) => e.end.as_deref()?,
+			Expr::RawAddr(e) => &e.expr,
+			Expr::Reference(e) => &e.expr,
+			Expr::Return(e) => e.expr.as_deref()?,
+			Expr::Unary(e) => &e.expr,
+			Expr::Yield(e) => e.expr.as_deref()?,
+			_ => return None,
+		};
+	}
+}
+
+fn contains_dollar(tts :&[Tt]) -> bool {
+	let mut groups = Vec::new();
+	let mut tts = tts.to_vec();
+	loop {
+		for tt in tts {
+			match tt {
+				Tt::Punct(p) if p.as_char() == '$' => return true,
+				Tt::Group(gr) => groups.push(gr),
+				_ => (),
+			}
+		}
+		tts = match groups.pop() {
+			Some(gr) => gr.stream().into_iter().collect(),
+			None => return false,
+		};
+	}
+}
+
+fn token_count(expr :&Expr) -> usize {
+	expr.to_token_stream().into_iter().count()
+}
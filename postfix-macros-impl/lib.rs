@@ -25,110 +25,1125 @@ postfix_macros! {
 }
 ```
 
+The receiver is passed as the first argument to the macro,
+unless there are arguments consisting of just `$self`, in which
+case these get replaced by the receiver:
+
+```
+# use postfix_macros_impl::postfix_macros;
+postfix_macros! {
+	"world".format!("hello {}", $self).assert_eq!("hello world");
+}
+```
+
+The elements of tuple receivers can be passed as
+separate arguments by using `..` as an argument:
+
+```
+# use postfix_macros_impl::postfix_macros;
+postfix_macros! {
+	(2 + 2, 4).assert_eq!(..);
+	(1, 2).format!("{}, {}, {}", .., 3).assert_eq!("1, 2, 3");
+}
+```
+
+Parenthesized expressions like `(a + b)` are not tuples,
+so they are not spread.
+
+If the macro can't figure out where the expression
+a postfix macro is invoked on starts, it emits a
+compile error pointing to the invocation, asking
+you to put the expression in parentheses.
+The invocation is left as-is and the rest of the
+block is still rewritten, so that all such errors
+get reported together:
+
+```compile_fail
+# use postfix_macros_impl::postfix_macros;
+postfix_macros! {
+	.dbg!();
+}
+```
+
+Receivers consisting of more than one token are wrapped
+in a `{}` block before being passed to the macro.
+This moves values out of place expressions like `a.b[0]`,
+which is a problem for macros that need a place, like
+`addr_of!`. The `#![postfix(wrap = "paren")]` attribute
+makes the receivers in the block it is put at the top of
+be wrapped in parentheses instead. It can be put at the
+top of the macro input, or at the top of any block inside it:
+
+```
+# use postfix_macros_impl::postfix_macros;
+macro_rules! take {
+	($v:expr) => { std::mem::take(&mut $v) };
+}
+postfix_macros! {
+	#![postfix(wrap = "paren")]
+	let mut names = vec![String::from("hello")];
+	names[0].take!().assert_eq!("hello");
+	names[0].assert_eq!("");
+}
+```
+
+Macros that mention the receiver multiple times
+evaluate it multiple times, which is surprising
+for receivers with side effects. The `#![postfix(eval_once)]`
+attribute binds the receiver to a variable first.
+Receivers that are a single variable name or literal are
+passed as they are, while other place expressions
+like `a.b` or `v[0]` get moved into the binding,
+unless they are `Copy`:
+
+```
+# use postfix_macros_impl::postfix_macros;
+macro_rules! twice {
+	($v:expr) => { ($v, $v) };
+}
+postfix_macros! {
+	#![postfix(eval_once)]
+	let mut numbers = 0..;
+	numbers.next().twice!().assert_eq!((Some(0), Some(0)));
+}
+```
+
+The macros that can be used as postfix macros can be restricted
+with `#![postfix(only(..))]` and `#![postfix(deny(..))]`, which
+take a list of macro paths as they are written in the invocations,
+like `only(dbg, log::info)`. In nested blocks, they narrow down
+the macros allowed by the outer block. Invocations of other macros
+are left untouched. With the `#![postfix(strict)]` attribute, they are
+reported as errors instead:
+
+```compile_fail
+# use postfix_macros_impl::postfix_macros;
+postfix_macros! {
+	#![postfix(strict, deny(dbg))]
+	42.dbg!();
+}
+```
+
+In strict mode, receivers whose start can't be determined from
+the grammar alone are reported as errors as well, instead of
+being guessed. For example, the receiver below might be `(x)`
+if the `match` is a statement, or the entire expression if
+the `match` is called:
+
+```compile_fail
+# use postfix_macros_impl::postfix_macros;
+postfix_macros! {
+	#![postfix(strict)]
+	let x = 4;
+	match x { _ => () } (x).dbg!();
+}
+```
+
+Tokens passed to a `verbatim!{..}` marker are not rewritten.
+The marker is kept, so it expands to the tokens as they are,
+just like outside of `postfix_macros!`. The arguments of
+`stringify!`, `concat!`, `quote!` and `quote_spanned!` are never
+rewritten either. Further macros can be added to that list
+via `#![postfix(verbatim(..))]`.
+
+```
+# use postfix_macros_impl::postfix_macros;
+macro_rules! tokens {
+	($($t:tt)*) => { stringify!($($t)*) };
+}
+postfix_macros! {
+	#![postfix(verbatim(tokens))]
+	let s = tokens!(x.foo!()).replace(' ', "");
+	s.assert_eq!("x.foo!()");
+	let s = stringify!(x.foo!()).replace(' ', "");
+	s.assert_eq!("x.foo!()");
+}
+```
+
+Macros listed in `#![postfix(chain(..))]`, like `then_else!`
+by default, continue the previous invocation of a chain.
+If their receiver is a postfix macro invocation, they get
+its path, receiver and parameters passed instead, in
+the form `@postfix_chain [PATH] (RECEIVER) (PARAMETERS)`,
+followed by their own parameters, if any:
+
+```
+# use postfix_macros_impl::postfix_macros;
+macro_rules! attempt {
+	($v:expr, $f:expr) => { $v.map($f).ok() };
+}
+macro_rules! otherwise {
+	(@postfix_chain [attempt] ($v:expr) ($f:expr), $default:expr) => {
+		$v.map($f).unwrap_or($default)
+	};
+}
+postfix_macros! {
+	#![postfix(chain(otherwise))]
+	"42".parse::<u8>().attempt!(|v| v + 1).assert_eq!(Some(43));
+	"42".parse::<u8>().attempt!(|v| v + 1).otherwise!(0).assert_eq!(43);
+	"no".parse::<u8>().attempt!(|v| v + 1).otherwise!(0).assert_eq!(0);
+}
+```
+
+Settings can be combined, like in `#![postfix(wrap = "paren", eval_once)]`.
+
+The `postfix_include!("path/to/file.rs")` macro rewrites the
+contents of a file, relative to the directory of the crate's
+`Cargo.toml`, like `postfix_macros!` would. It can be used
+where items or statements are expected.
+
+```compile_fail
+# use postfix_macros_impl::postfix_include;
+postfix_include!("does/not/exist.rs");
+# fn main() {}
+```
 */
 #![forbid(unsafe_code)]
+#![allow(clippy::tabs_in_doc_comments)]
 
 extern crate proc_macro;
 use proc_macro::{TokenStream, TokenTree as Tt, Punct, Group, Spacing,
-	Delimiter};
+	Delimiter, Ident, Literal, Span};
+use std::rc::Rc;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "full-parse")]
+mod full_parse;
 
 #[proc_macro]
 pub fn postfix_macros(stream :TokenStream) -> TokenStream {
-	let mut vis = Visitor;
+	let mut vis = Visitor::new();
 	let res = vis.visit_stream(stream);
-	//println!("{}", res);
-	res
+	vis.finish(res)
+}
+
+#[proc_macro]
+pub fn postfix_include(stream :TokenStream) -> TokenStream {
+	let (path, span) = match include_path(stream) {
+		Ok(path) => path,
+		Err(e) => return e.to_compile_error(),
+	};
+	let contents = match std::fs::read_to_string(&path) {
+		Ok(contents) => contents,
+		Err(e) => return Error::new(span, format!("couldn't read `{}`: {}",
+			path.display(), e)).to_compile_error(),
+	};
+	// There is no stable way to create spans pointing into
+	// another file, so the tokens get the span of the invocation.
+	let stream = match contents.parse::<TokenStream>() {
+		Ok(stream) => stream,
+		Err(e) => return Error::new(span, format!("couldn't parse `{}`: {}",
+			path.display(), e)).to_compile_error(),
+	};
+	// Make the compiler track the file, so that
+	// the crate gets rebuilt when it changes.
+	// It comes first, as the file might end in
+	// an expression without a `;`.
+	let path = Literal::string(&path.to_string_lossy());
+	let mut res = format!("const _ :&str = include_str!({});", path)
+		.parse::<TokenStream>()
+		.unwrap();
+	let mut vis = Visitor::new();
+	res.extend(vis.visit_stream(stream));
+	vis.finish(res)
+}
+
+/// Parses the argument of `postfix_include!`, and
+/// resolves it relative to `CARGO_MANIFEST_DIR`.
+///
+/// Returns the path and the span of the argument.
+fn include_path(stream :TokenStream) -> Result<(PathBuf, Span), Error> {
+	let tts = stream.into_iter().collect::<Vec<_>>();
+	let lit = match &tts[..] {
+		[Tt::Literal(lit)] => lit,
+		_ => {
+			let span = tts.first().map(|tt| tt.span()).unwrap_or_else(Span::call_site);
+			return Err(Error::new(span,
+				"expected a string literal with the path of the file".to_string()));
+		},
+	};
+	let path = string_value(lit).ok_or_else(|| Error::new(lit.span(),
+		"expected a string literal without escapes with the path \
+		of the file; use `/` or a raw string literal".to_string()))?;
+	let dir = std::env::var_os("CARGO_MANIFEST_DIR").ok_or_else(|| Error::new(lit.span(),
+		"`CARGO_MANIFEST_DIR` is not set, build with cargo".to_string()))?;
+	Ok((Path::new(&dir).join(path), lit.span()))
+}
+
+/// Returns the value of a string literal without
+/// escapes, like `"a/b.rs"` or `r"a\b.rs"`.
+fn string_value(lit :&Literal) -> Option<String> {
+	let lit = lit.to_string();
+	let (raw, hashes) = match lit.strip_prefix('r') {
+		Some(rest) => (true, rest.len() - rest.trim_start_matches('#').len()),
+		None => (false, 0),
+	};
+	let start = raw as usize + hashes;
+	let value = lit.get(start + 1..lit.len().checked_sub(hashes + 1)?)?;
+	let quoted = lit[start..].starts_with('"') &&
+		lit[..lit.len() - hashes].ends_with('"');
+	if !quoted || (!raw && value.contains('\\')) {
+		return None;
+	}
+	Some(value.to_string())
 }
 
-struct Visitor;
+/// Like `postfix_macros!`, but additionally determines each
+/// receiver with the heuristic, and reports an error if the
+/// result differs from the one of the full parser.
+///
+/// Used to test the heuristic.
+#[cfg(feature = "full-parse")]
+#[doc(hidden)]
+#[proc_macro]
+pub fn __postfix_macros_differential(stream :TokenStream) -> TokenStream {
+	let mut vis = Visitor::new();
+	vis.differential = true;
+	let res = vis.visit_stream(stream);
+	vis.finish(res)
+}
+
+/// Measures how long rewriting generated inputs of
+/// different sizes takes. Used by `benches/scaling.rs`.
+///
+/// Expands to an array of `(input kind, size, nanoseconds)` tuples,
+/// which is empty if the proc macro was built without optimizations,
+/// as the measurements would take very long.
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+#[proc_macro]
+pub fn __postfix_macros_bench(_stream :TokenStream) -> TokenStream {
+	if cfg!(debug_assertions) {
+		return "[]".parse().unwrap();
+	}
+	/// Generates an input of the given size
+	type Generator = fn(usize) -> String;
+	let sizes = &[10_000, 20_000, 40_000];
+	let kinds :&[(&str, &[usize], Generator)] = &[
+		// Many statements in one block
+		("statements", sizes, |n| (0..n)
+			.map(|i| format!("let v{0} = a.b[{0}].c().foo!({0}).bar!(); \
+				if v {{ v.baz!() }} else {{ w }}.qux!();", i))
+			.collect()),
+		// One long builder chain
+		("chain", sizes, |n| format!("builder{};", (0..n)
+			.map(|i| format!(".step({0}).apply!({0})", i))
+			.collect::<String>())),
+		// Many match arms, whose receivers
+		// aren't preceded by a statement
+		("match arms", sizes, |n| format!("match i {{ {} _ => 0 }};", (0..n)
+			.map(|i| format!("{0} => x.add!({0}),", i))
+			.collect::<String>())),
+		// Many comparisons, whose `>` could also
+		// close a generic argument list
+		("comparisons", sizes, |n| format!("let v = [{}];", (0..n)
+			.map(|_| "a > b.id!(), ")
+			.collect::<String>())),
+		// One long sum, whose operands
+		// are all in the same statement
+		("operators", sizes, |n| format!("let v = {}0;", (0..n)
+			.map(|_| "a.id!() + ")
+			.collect::<String>())),
+		// Deeply nested blocks. Deeper nesting
+		// overflows the stack of rustc's lexer.
+		("nesting", &[2_500, 5_000, 10_000], |n| format!("{}{}",
+			"{ x.foo!(); ".repeat(n), "}".repeat(n))),
+	];
+	let mut entries = Vec::new();
+	for (kind, sizes, gen) in kinds {
+		for &size in *sizes {
+			let input = gen(size).parse::<TokenStream>().unwrap();
+			// Take the fastest of a few runs
+			let nanos = (0..3)
+				.map(|_| {
+					let input = input.clone();
+					let start = std::time::Instant::now();
+					let mut vis = Visitor::new();
+					let res = vis.visit_stream(input);
+					let nanos = start.elapsed().as_nanos();
+					drop(res);
+					nanos
+				})
+				.min()
+				.unwrap();
+			entries.push(format!("({:?}, {}usize, {}u128)", kind, size, nanos));
+		}
+	}
+	format!("[{}]", entries.join(", ")).parse().unwrap()
+}
+
+/// An error encountered while rewriting a postfix macro invocation.
+///
+/// It gets reported through a `compile_error!` invocation
+/// that spans from `start` to `end`.
+struct Error {
+	start :Span,
+	end :Span,
+	msg :String,
+}
+
+impl Error {
+	fn new(span :Span, msg :String) -> Self {
+		Error {
+			start : span,
+			end : span,
+			msg,
+		}
+	}
+	fn to_compile_error(&self) -> TokenStream {
+		// There is no stable way to join spans, so we
+		// use the same trick as syn: the error is reported
+		// for the range from the first to the last token
+		// of the compile_error invocation.
+		let ident = Ident::new("compile_error", self.start);
+		let mut bang = Punct::new('!', Spacing::Alone);
+		bang.set_span(self.start);
+		let mut msg = Literal::string(&self.msg);
+		msg.set_span(self.end);
+		let mut group = Group::new(Delimiter::Brace, Tt::Literal(msg).into());
+		group.set_span(self.end);
+		vec![Tt::Ident(ident), Tt::Punct(bang), Tt::Group(group)]
+			.into_iter()
+			.collect()
+	}
+}
+
+struct Visitor {
+	/// Errors of `#![postfix(..)]` attributes.
+	///
+	/// Errors of invocations are reported in place of the
+	/// invocation instead, and the rewrite continues.
+	errors :Vec<Error>,
+	/// Whether to compare the results of the
+	/// full parser with the ones of the heuristic.
+	#[cfg(feature = "full-parse")]
+	differential :bool,
+}
 
 impl Visitor {
+	fn new() -> Self {
+		Visitor {
+			errors : Vec::new(),
+			#[cfg(feature = "full-parse")]
+			differential : false,
+		}
+	}
+	fn finish(self, res :TokenStream) -> TokenStream {
+		// Report the attribute errors in front of
+		// the rewritten code.
+		let mut errors = self.errors.iter()
+			.map(Error::to_compile_error)
+			.collect::<TokenStream>();
+		errors.extend(res);
+		errors
+	}
 	fn visit_stream(&mut self, stream :TokenStream) -> TokenStream {
-		let mut res = Vec::new();
-		let mut stream_iter = stream.into_iter();
-		while let Some(tt) = stream_iter.next() {
-			match tt {
-				Tt::Group(group) => {
-					let mut postfix_macro = false;
-					{
-						let last_three = res.rchunks(3).next();
-						if let Some(&[Tt::Punct(ref p1), Tt::Ident(_), Tt::Punct(ref p2)]) = last_three {
-							if (p1.as_char(), p1.spacing(), p2.as_char(), p2.spacing()) == ('.', Spacing::Alone, '!', Spacing::Alone) {
-								postfix_macro = true;
+		// Keep the groups we are in on the heap instead of
+		// recursing into them, so that deeply nested code
+		// doesn't overflow the stack.
+		let top = self.new_frame(stream, Delimiter::None, Span::call_site(),
+			None, Rc::new(Config::default()));
+		let mut stack = vec![top];
+		loop {
+			let frame = stack.last_mut().unwrap();
+			match frame.tokens.next() {
+				Some(Tt::Group(group)) => {
+					let mac_len = postfix_macro_length(&frame.res);
+					let path_len = mac_len.or_else(|| macro_path_length(&frame.res));
+					if let Some(path_len) = path_len {
+						let path_start = frame.res.len() - path_len;
+						let path = path_string(&frame.res[path_start..frame.res.len() - 1]);
+						if frame.config.verbatim.contains(&path) {
+							// Don't rewrite the arguments of macros
+							// like `stringify!` that take raw tokens
+							match mac_len {
+								Some(mac_len) => self.rewrite_invocation(frame, mac_len, group),
+								None => frame.res.push(Tt::Group(group)),
 							}
+							continue;
 						}
 					}
-					let group = if postfix_macro {
-						// Remove the ! and macro ident
-						let mac_bang = res.pop().unwrap();
-						let mac = res.pop().unwrap();
-						// Remove the . before the macro
-						res.pop().unwrap();
-
-						// Walk the entire chain of tt's that
-						// form the expression we want to feed to the macro.
-						let expr_len = expression_length(&res);
-
-						if expr_len == 0 {
-							panic!("expected something before the postfix macro invocation");
+					// Groups are visited before they get passed to
+					// the postfix macro, so that each token gets
+					// visited only once.
+					let mut config = frame.config.clone();
+					if config.placeholder && is_macro_rules_body(&frame.res) {
+						// `$self` is a metavariable there
+						Rc::make_mut(&mut config).placeholder = false;
+					}
+					let frame = self.new_frame(group.stream(), group.delimiter(),
+						group.span(), mac_len, config);
+					stack.push(frame);
+				},
+				Some(tt) => {
+					frame.res.push(tt);
+				},
+				None => {
+					let frame = stack.pop().unwrap();
+					let stream = frame.res.into_iter().collect();
+					let parent = match stack.last_mut() {
+						Some(parent) => parent,
+						None => return stream,
+					};
+					let mut group = Group::new(frame.delimiter, stream);
+					group.set_span(frame.span);
+					if let Some(mac_len) = frame.mac_len {
+						self.rewrite_invocation(parent, mac_len, group);
+					} else {
+						parent.res.push(Tt::Group(group));
+					}
+				},
+			}
+		}
+	}
+	/// Creates the frame to visit the tokens of a group with.
+	///
+	/// Blocks may start with `#![postfix(..)]` attributes that
+	/// change the configuration for the block. They are
+	/// applied to `config`, and removed from the tokens.
+	fn new_frame(&mut self, stream :TokenStream, delimiter :Delimiter, span :Span,
+			mac_len :Option<usize>, mut config :Rc<Config>) -> Frame {
+		let mut tokens = stream.into_iter().collect::<Vec<_>>();
+		if matches!(delimiter, Delimiter::Brace | Delimiter::None) {
+			let mut pos = 0;
+			while let [Tt::Punct(hash), Tt::Punct(bang), Tt::Group(attr), ..] = &tokens[pos..] {
+				if hash.as_char() != '#' || bang.as_char() != '!' ||
+						attr.delimiter() != Delimiter::Bracket {
+					break;
+				}
+				match postfix_attribute_args(attr) {
+					Some(args) => {
+						if let Err(e) = Rc::make_mut(&mut config).apply(args) {
+							self.errors.push(e);
 						}
-						//println!("  -> built");
+						tokens.drain(pos..pos + 3);
+					},
+					// Some other inner attribute, like `#![allow(..)]`
+					None => pos += 3,
+				}
+			}
+		}
+		Frame {
+			tokens : tokens.into_iter(),
+			res : Vec::new(),
+			delimiter,
+			span,
+			mac_len,
+			config,
+			last_invocation : None,
+			angles : AngleBrackets::default(),
+		}
+	}
+	/// Rewrites the postfix macro invocation whose
+	/// `.`, path and `!` are at the end of the visited
+	/// tokens of `frame`, and whose parameters are
+	/// passed in `group`.
+	///
+	/// `mac_len` is the number of tt's of the path and `!`.
+	///
+	/// The group must have been visited already.
+	///
+	/// Pushes the rewritten invocation to the visited tokens.
+	/// If the receiver can't be determined, replaces the
+	/// invocation with an error instead.
+	fn rewrite_invocation(&mut self, frame :&mut Frame, mac_len :usize, group :Group) {
+		let config = frame.config.clone();
+		frame.angles.update(&frame.res);
+		let res = &mut frame.res;
+		let dot_pos = res.len() - mac_len - 1;
+		let dot = &res[dot_pos];
+		let mac_bang = &res[res.len() - 1];
 
-						// Build the group
-						let gr = self.visit_group(group);
-						let arg_tokens = &res[(res.len() - expr_len)..];
-						let gr = prepend_macro_arg_to_group(arg_tokens, gr);
-						res.truncate(res.len() - expr_len);
+		let path = path_string(&res[dot_pos + 1..res.len() - 1]);
+		if !config.allows(&path) {
+			if config.strict {
+				let error = Error {
+					start : dot.span(),
+					end : mac_bang.span(),
+					msg : format!("`{}` is not allowed as postfix macro here", path),
+				};
+				fail_invocation(frame, mac_len, error);
+				return;
+			}
+			res.push(Tt::Group(group));
+			return;
+		}
 
-						// Add back the macro ident and bang
-						res.push(mac);
-						res.push(mac_bang);
+		// The previous invocation, which might be part of the receiver
+		let prev = frame.last_invocation.as_ref()
+			.map(|last| (last.start, last.end))
+			.filter(|&(start, end)| start < end && end <= dot_pos);
 
-						/*println!("res so far: {}",
-							res.iter().cloned().collect::<TokenStream>());*/
+		// Walk the entire chain of tt's that
+		// form the expression we want to feed to the macro.
+		let expr_len = match self.receiver_length(&res[..dot_pos], &frame.angles,
+				prev, config.strict) {
+			Ok(0) => {
+				let error = Error {
+					start : dot.span(),
+					end : mac_bang.span(),
+					msg : format!("expected an expression before the postfix macro \
+						invocation `.{}!`", path),
+				};
+				fail_invocation(frame, mac_len, error);
+				return;
+			},
+			Ok(expr_len) => expr_len,
+			Err(e) => {
+				let error = Error {
+					end : mac_bang.span(),
+					.. e
+				};
+				fail_invocation(frame, mac_len, error);
+				return;
+			},
+		};
+		//println!("  -> built");
 
-						gr
-					} else {
-						group
-					};
-					let tt = Tt::Group(self.visit_group(group));
-					res.push(tt);
+		// Remove the ! and macro path
+		let mac = res.split_off(dot_pos + 1);
+		// Remove the . before the macro
+		res.pop().unwrap();
+
+		let start = res.len() - expr_len;
+		frame.angles.truncate(start);
+		// The span for the tokens we add
+		let span = receiver_span(&res[start..]);
+		let last = frame.last_invocation.take();
+		frame.last_invocation = Some(Invocation {
+			start,
+			end : 0,
+			path : mac[..mac.len() - 1].to_vec(),
+			receiver : res[start..].to_vec(),
+			args : group.stream(),
+		});
+
+		// Continuation macros like `then_else!` get the previous
+		// invocation of the chain passed in a structured form:
+		// path!(@postfix_chain [PREV_PATH] (PREV_RECEIVER) (PREV_ARGS), ARGS)
+		let last = last.filter(|last| last.start == start && last.end == res.len());
+		if let (Some(last), true) = (last, config.chain.contains(&path)) {
+			res.truncate(start);
+			let receiver = match &last.receiver[..] {
+				[Tt::Group(g)] => Tt::Group(receiver_group(g)).into(),
+				receiver => receiver.iter().cloned().collect(),
+			};
+			let mut args = vec![
+				spanned(Tt::Punct(Punct::new('@', Spacing::Alone)), span),
+				Tt::Ident(Ident::new("postfix_chain", span)),
+				spanned(Tt::Group(Group::new(Delimiter::Bracket,
+					last.path.into_iter().collect())), span),
+				spanned(Tt::Group(Group::new(Delimiter::Parenthesis, receiver)), span),
+				spanned(Tt::Group(Group::new(Delimiter::Parenthesis, last.args)), span),
+			];
+			if !group.stream().is_empty() {
+				args.push(spanned(Tt::Punct(Punct::new(',', Spacing::Alone)), span));
+				args.extend(group.stream());
+			}
+			let mut gr = Group::new(group.delimiter(), args.into_iter().collect());
+			gr.set_span(group.span());
+			res.extend(mac);
+			res.push(Tt::Group(gr));
+			frame.finish_invocation();
+			return;
+		}
+
+		// Build the group
+		let arg_tokens = &res[start..];
+		// Tuple receivers get spread into multiple
+		// arguments if there is a `..` argument.
+		let tuple = match arg_tokens {
+			[Tt::Group(g)] if is_tuple(g) &&
+					split_args(group.stream()).iter().any(|arg| is_spread_marker(arg)) => {
+				Some(g.clone())
+			},
+			_ => None,
+		};
+		let receiver = wrap_receiver(arg_tokens, config.wrap, span);
+		// A variable name or literal is evaluated without
+		// side effects, and binding it would move it.
+		let single_token = matches!(arg_tokens, [Tt::Ident(_)] | [Tt::Literal(_)]);
+		res.truncate(res.len() - expr_len);
+
+		if config.eval_once && !single_token {
+			// Bind the receiver to a fresh variable, so that
+			// it's evaluated once, regardless of how often the
+			// macro mentions it. We use match instead of let
+			// so that temporaries live until the end of the
+			// invocation, like they do without the binding:
+			// (match RECEIVER { binding => path!(binding, ..) })
+			let (receiver, pattern, elements) = match tuple {
+				Some(tuple) => {
+					// Bind each element of the tuple on its own:
+					// (match (a, b) { (b0, b1) => path!(b0, b1) })
+					let (elements, trailing_comma) = tuple_elements(&tuple);
+					let bindings = (0..elements.len())
+						.map(|i| vec![Tt::Ident(Ident::new(
+							&format!("__postfix_receiver_{}", i),
+							Span::mixed_site().located_at(span)))])
+						.collect::<Vec<_>>();
+					let mut pattern = join_args(bindings.clone(), span);
+					if trailing_comma {
+						pattern.extend(std::iter::once(spanned(
+							Tt::Punct(Punct::new(',', Spacing::Alone)), span)));
+					}
+					let pattern = spanned(Tt::Group(Group::new(Delimiter::Parenthesis, pattern)), span);
+					(Tt::Group(receiver_group(&tuple)), pattern.clone(), Some(bindings))
+				},
+				None => {
+					let binding = Ident::new("__postfix_receiver",
+						Span::mixed_site().located_at(span));
+					(receiver, Tt::Ident(binding), None)
+				},
+			};
+			let gr = prepend_macro_arg_to_group(pattern.clone(), elements,
+				config.placeholder, group);
+			let mut arm = vec![
+				pattern,
+				spanned(Tt::Punct(Punct::new('=', Spacing::Joint)), span),
+				spanned(Tt::Punct(Punct::new('>', Spacing::Alone)), span),
+			];
+			arm.extend(mac);
+			arm.push(Tt::Group(gr));
+			let arms = Group::new(Delimiter::Brace, arm.into_iter().collect());
+			// The parens make it possible to continue the
+			// method chain if the match is a statement.
+			let mat = vec![
+				Tt::Ident(Ident::new("match", span)),
+				receiver,
+				spanned(Tt::Group(arms), span),
+			];
+			res.push(spanned(Tt::Group(Group::new(Delimiter::Parenthesis,
+				mat.into_iter().collect())), span));
+			frame.finish_invocation();
+			return;
+		}
+
+		// Add back the macro path and bang
+		res.extend(mac);
+
+		/*println!("res so far: {}",
+			res.iter().cloned().collect::<TokenStream>());*/
+
+		let elements = tuple.map(|tuple| tuple_elements(&tuple).0);
+		res.push(Tt::Group(prepend_macro_arg_to_group(receiver, elements,
+			config.placeholder, group)));
+		frame.finish_invocation();
+	}
+	/// Determines the number of tt's at the end of `tts`
+	/// that form the receiver of a postfix macro invocation.
+	///
+	/// In strict mode, receivers that the grammar
+	/// doesn't clearly delimit are rejected.
+	///
+	/// `angles` is the matching of the angle brackets in
+	/// `tts`, and `prev` is the range of the rewritten
+	/// previous invocation in `tts`, if there is one.
+	#[cfg(not(feature = "full-parse"))]
+	fn receiver_length(&self, tts :&[Tt], angles :&AngleBrackets,
+			_prev :Option<(usize, usize)>, strict :bool) -> Result<usize, Error> {
+		expression_length(tts, angles, strict)
+	}
+	/// Determines the number of tt's at the end of `tts`
+	/// that form the receiver of a postfix macro invocation.
+	///
+	/// In strict mode, receivers that the grammar
+	/// doesn't clearly delimit are rejected.
+	///
+	/// `angles` is the matching of the angle brackets in
+	/// `tts`, and `prev` is the range of the rewritten
+	/// previous invocation in `tts`, if there is one.
+	#[cfg(feature = "full-parse")]
+	fn receiver_length(&self, tts :&[Tt], angles :&AngleBrackets,
+			prev :Option<(usize, usize)>, strict :bool) -> Result<usize, Error> {
+		// Reject the same receivers as without the full
+		// parser, so that strict code compiles either way.
+		if strict {
+			expression_length(tts, angles, true)?;
+		}
+		// If syn can't parse the code, e.g. because of
+		// macro_rules metavariables, use the heuristic.
+		let len = match full_parse::receiver_length(tts, angles, prev) {
+			Some(len) => len,
+			None => return expression_length(tts, angles, strict),
+		};
+		if self.differential {
+			let heuristic = match expression_length(tts, angles, strict) {
+				Ok(h) if h == len => return Ok(len),
+				Ok(h) => format!("`{}`", tokens_to_string(&tts[tts.len() - h..])),
+				Err(e) => format!("error \"{}\"", e.msg),
+			};
+			return Err(Error::new(tts[tts.len() - len].span(),
+				format!("full parser found receiver `{}`, but heuristic found {}",
+					tokens_to_string(&tts[tts.len() - len..]), heuristic)));
+		}
+		Ok(len)
+	}
+}
+
+/// A group whose tokens are being visited
+struct Frame {
+	/// The tokens that haven't been visited yet
+	tokens :std::vec::IntoIter<Tt>,
+	/// The visited tokens
+	res :Vec<Tt>,
+	delimiter :Delimiter,
+	span :Span,
+	/// If the group contains the parameters of a postfix
+	/// macro invocation, the number of tt's of the path
+	/// and `!` at the end of the parent's visited tokens.
+	mac_len :Option<usize>,
+	/// The configuration for the postfix macro
+	/// invocations inside the group
+	config :Rc<Config>,
+	/// The last postfix macro invocation that
+	/// was rewritten inside the group
+	last_invocation :Option<Invocation>,
+	/// The matching of the angle brackets in the visited tokens
+	angles :AngleBrackets,
+}
+
+impl Frame {
+	/// Records where the rewritten tokens of
+	/// the last invocation end
+	fn finish_invocation(&mut self) {
+		if let Some(last) = &mut self.last_invocation {
+			last.end = self.res.len();
+		}
+	}
+}
+
+/// The matching of `<` and `>` in a sequence of tt's
+///
+/// It gets extended as tokens are added, so that finding the
+/// `<` of a `>` doesn't need to go through all the tokens
+/// before it again. This keeps e.g. a long list of comparisons
+/// like `[a > b.foo!(), a > b.foo!(), ..]` linear.
+#[derive(Default)]
+struct AngleBrackets {
+	/// One entry for each tt the matching has been computed for
+	entries :Vec<AngleEntry>,
+}
+
+/// The angle brackets around a tt
+struct AngleEntry {
+	/// The position of the innermost `<` that
+	/// is still open after the tt, if any
+	open :Option<usize>,
+	/// For a `>`, the position of the `<` it closes,
+	/// and for a `<`, the innermost `<` open before it
+	link :Option<usize>,
+}
+
+impl AngleBrackets {
+	fn new(tts :&[Tt]) -> Self {
+		let mut angles = AngleBrackets::default();
+		angles.update(tts);
+		angles
+	}
+	/// Computes the matching for the tt's added to
+	/// the end of `tts` since the last update.
+	///
+	/// The tt's before must not have changed,
+	/// or be removed through `truncate` first.
+	fn update(&mut self, tts :&[Tt]) {
+		for i in self.entries.len()..tts.len() {
+			let open = self.entries.last().and_then(|e| e.open);
+			let p = match &tts[i] {
+				Tt::Punct(p) => p,
+				_ => {
+					self.entries.push(AngleEntry { open, link : None });
+					continue;
+				},
+			};
+			let before = match tts[..i].last() {
+				Some(Tt::Punct(b)) if b.spacing() == Spacing::Joint => Some(b.as_char()),
+				_ => None,
+			};
+			let entry = match p.as_char() {
+				'<' => AngleEntry { open : Some(i), link : open },
+				// The `->` in `Fn() -> u8`
+				'>' if before == Some('-') => AngleEntry { open, link : None },
+				'>' => AngleEntry {
+					open : open.and_then(|o| self.entries[o].link),
+					link : open,
+				},
+				// These can't appear in generic arguments, so
+				// a `>` after them can't close a `<` before them.
+				// A lone `=` binds an associated type, like in
+				// `Iterator<Item = u8>`, but `==` or `=>` can't
+				// appear in generic arguments.
+				';' | '|' | '.' => AngleEntry { open : None, link : None },
+				'=' if p.spacing() == Spacing::Joint || before.is_some() => {
+					AngleEntry { open : None, link : None }
+				},
+				_ => AngleEntry { open, link : None },
+			};
+			self.entries.push(entry);
+		}
+	}
+	/// Removes the matching of the tt's from `len` on
+	fn truncate(&mut self, len :usize) {
+		self.entries.truncate(len);
+	}
+	/// Returns the position of the `<` that the `>` at `pos` closes
+	fn opening(&self, pos :usize) -> Option<usize> {
+		self.entries[pos].link
+	}
+}
+
+/// A postfix macro invocation, as it was written
+struct Invocation {
+	/// The position of the rewritten invocation
+	/// in the visited tokens of the frame
+	start :usize,
+	/// The position after the rewritten invocation
+	end :usize,
+	/// The macro path, without the `!`
+	path :Vec<Tt>,
+	receiver :Vec<Tt>,
+	args :TokenStream,
+}
+
+/// Configuration of the rewriting, set through
+/// `#![postfix(..)]` attributes.
+#[derive(Clone)]
+struct Config {
+	/// The delimiter to wrap receivers
+	/// consisting of multiple tt's in
+	wrap :Delimiter,
+	/// Whether to bind the receiver to a variable
+	/// before passing it to the macro
+	eval_once :bool,
+	/// If set, the paths of the only macros
+	/// that may be used as postfix macros
+	only :Option<Vec<String>>,
+	/// The paths of macros that may not
+	/// be used as postfix macros
+	deny :Vec<String>,
+	/// Whether to report errors instead of
+	/// leaving code untouched
+	strict :bool,
+	/// The paths of macros whose arguments
+	/// don't get rewritten
+	verbatim :Vec<String>,
+	/// The paths of macros that get the previous
+	/// invocation of the chain passed
+	chain :Vec<String>,
+	/// Whether `$self` arguments get replaced by the receiver.
+	///
+	/// Not set through attributes, but turned off
+	/// inside `macro_rules!` definitions.
+	placeholder :bool,
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Config {
+			wrap : Delimiter::Brace,
+			eval_once : false,
+			only : None,
+			deny : Vec::new(),
+			strict : false,
+			verbatim : [
+				"stringify", "std::stringify", "core::stringify",
+				"concat", "std::concat", "core::concat",
+				"quote", "quote::quote", "quote_spanned", "quote::quote_spanned",
+				"verbatim", "postfix_macros::verbatim", "::postfix_macros::verbatim",
+			].iter().map(|p| p.to_string()).collect(),
+			chain : vec!["then_else".to_string(), "postfix_macros::then_else".to_string()],
+			placeholder : true,
+		}
+	}
+}
+
+impl Config {
+	/// Whether the macro with the given path,
+	/// like `log::info`, may be used as postfix macro
+	fn allows(&self, path :&str) -> bool {
+		let allowed = match &self.only {
+			Some(only) => only.iter().any(|p| p == path),
+			None => true,
+		};
+		allowed && !self.deny.iter().any(|p| p == path)
+	}
+	/// Applies the settings passed to a `#![postfix(..)]` attribute,
+	/// like `wrap = "paren"` or `only(dbg, log::info)`.
+	fn apply(&mut self, args :TokenStream) -> Result<(), Error> {
+		let args = args.into_iter().collect::<Vec<_>>();
+		for setting in args.split(|tt| matches!(tt, Tt::Punct(p) if p.as_char() == ',')) {
+			match setting {
+				[] => (),
+				[Tt::Ident(key)] if key.to_string() == "eval_once" => {
+					self.eval_once = true;
+				},
+				[Tt::Ident(key)] if key.to_string() == "strict" => {
+					self.strict = true;
+				},
+				[Tt::Ident(key), Tt::Group(paths)] if key.to_string() == "only" &&
+						paths.delimiter() == Delimiter::Parenthesis => {
+					let mut paths = macro_paths(paths)?;
+					// Nested blocks can only narrow down the list
+					if let Some(outer) = &self.only {
+						paths.retain(|p| outer.contains(p));
+					}
+					self.only = Some(paths);
 				},
-				Tt::Ident(id) => {
-					res.push(Tt::Ident(id));
+				[Tt::Ident(key), Tt::Group(paths)] if key.to_string() == "deny" &&
+						paths.delimiter() == Delimiter::Parenthesis => {
+					self.deny.extend(macro_paths(paths)?);
 				},
-				Tt::Punct(p) => {
-					res.push(Tt::Punct(p));
+				[Tt::Ident(key), Tt::Group(paths)] if key.to_string() == "verbatim" &&
+						paths.delimiter() == Delimiter::Parenthesis => {
+					self.verbatim.extend(macro_paths(paths)?);
 				},
-				Tt::Literal(lit) => {
-					res.push(Tt::Literal(lit));
+				[Tt::Ident(key), Tt::Group(paths)] if key.to_string() == "chain" &&
+						paths.delimiter() == Delimiter::Parenthesis => {
+					self.chain.extend(macro_paths(paths)?);
 				},
+				[Tt::Ident(key), Tt::Punct(eq), Tt::Literal(value)]
+						if key.to_string() == "wrap" && eq.as_char() == '=' => {
+					self.wrap = match value.to_string().as_str() {
+						"\"brace\"" => Delimiter::Brace,
+						"\"paren\"" => Delimiter::Parenthesis,
+						_ => return Err(Error::new(value.span(), format!(
+							"unknown wrapping style {}, expected \"brace\" or \"paren\"",
+							value))),
+					};
+				},
+				[tt, ..] => {
+					return Err(Error::new(tt.span(), format!(
+						"unknown postfix setting `{}`",
+						setting.iter().cloned().collect::<TokenStream>())));
+				},
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Parses the comma separated macro paths
+/// passed to settings like `only(dbg, log::info)`
+fn macro_paths(paths :&Group) -> Result<Vec<String>, Error> {
+	let mut res = Vec::new();
+	for path in split_args(paths.stream()) {
+		if path.is_empty() {
+			continue;
+		}
+		let is_path = path.len() == path_length(&path, &AngleBrackets::new(&path)) && path.iter()
+			.all(|tt| matches!(tt, Tt::Ident(_) | Tt::Punct(_)));
+		if !is_path {
+			return Err(Error::new(path[0].span(), format!(
+				"expected a macro path, found `{}`",
+				path.iter().cloned().collect::<TokenStream>())));
+		}
+		res.push(path.iter().map(|tt| tt.to_string()).collect());
+	}
+	Ok(res)
+}
+
+/// Checks whether the contents of an inner attribute
+/// are `postfix(..)`, and returns the arguments if so.
+fn postfix_attribute_args(attr :&Group) -> Option<TokenStream> {
+	let tts = attr.stream().into_iter().collect::<Vec<_>>();
+	match tts.as_slice() {
+		[Tt::Ident(id), Tt::Group(args)] if id.to_string() == "postfix" &&
+				args.delimiter() == Delimiter::Parenthesis => {
+			Some(args.stream())
+		},
+		_ => None,
+	}
+}
+
+/// Checks whether `tts` ends with the `.path!` part of a
+/// postfix macro invocation like `.foo!` or `.log::info!`,
+/// and returns the number of tt's of the path and `!` if so.
+fn postfix_macro_length(tts :&[Tt]) -> Option<usize> {
+	let len = macro_path_length(tts)?;
+	let pos = tts.len() - len;
+	// The `.` before the path, which must not be part of a `..`
+	match &tts[..pos] {
+		[.., Tt::Punct(p), Tt::Punct(dot)] if p.as_char() == '.' &&
+			p.spacing() == Spacing::Joint && dot.as_char() == '.' => None,
+		[.., Tt::Punct(dot)] if dot.as_char() == '.' => Some(len),
+		_ => None,
+	}
+}
+
+/// Checks whether `tts` ends with the `path!` part of
+/// a macro invocation, and returns the number of tt's
+/// of the path and `!` if so.
+fn macro_path_length(tts :&[Tt]) -> Option<usize> {
+	match tts.last() {
+		Some(Tt::Punct(p)) if p.as_char() == '!' && p.spacing() == Spacing::Alone => (),
+		_ => return None,
+	}
+	// Walk the path backwards, segment by segment
+	let mut pos = tts.len() - 1;
+	loop {
+		match tts[..pos].last() {
+			Some(Tt::Ident(_)) => pos -= 1,
+			_ => return None,
+		}
+		// `$crate` inside macro_rules definitions
+		if let Some(Tt::Punct(p)) = tts[..pos].last() {
+			if p.as_char() == '$' {
+				pos -= 1;
 			}
 		}
-		res.into_iter().collect()
+		match &tts[..pos] {
+			[.., Tt::Punct(c1), Tt::Punct(c2)] if c1.as_char() == ':' &&
+					c1.spacing() == Spacing::Joint && c2.as_char() == ':' => {
+				pos -= 2;
+				// Leading `::`, like in `::foo::bar!()`
+				if !matches!(tts[..pos].last(), Some(Tt::Ident(_))) {
+					break;
+				}
+			},
+			_ => break,
+		}
 	}
-	fn visit_group(&mut self, group :Group) -> Group {
-		let delim = group.delimiter();
-		let span = group.span();
-		let stream = self.visit_stream(group.stream());
-		let mut gr = Group::new(delim, stream);
-		gr.set_span(span);
-		gr
+	Some(tts.len() - pos)
+}
+
+/// Checks whether `tts` end with `macro_rules! name`,
+/// so that the following group is a macro definition.
+fn is_macro_rules_body(tts :&[Tt]) -> bool {
+	match tts {
+		[.., Tt::Ident(mr), Tt::Punct(bang), Tt::Ident(_)] => {
+			mr.to_string() == "macro_rules" && bang.as_char() == '!'
+		},
+		_ => false,
 	}
 }
 
+/// Turns a macro path into a string like `log::info`
+fn path_string(tts :&[Tt]) -> String {
+	tts.iter()
+		.map(|tt| tt.to_string())
+		.collect()
+}
 
 /// Walk the entire chain of tt's that
 /// form an expression that a postfix macro call
 /// would be part of.
 ///
 /// Returns the number of token tree items that
-/// belong to the expression.
-fn expression_length(tts :&[Tt]) -> usize {
+/// belong to the expression, or an error if
+/// the expression contains constructs that
+/// aren't supported.
+///
+/// In strict mode, it's also an error if the start
+/// of the expression can only be guessed.
+///
+/// `angles` must contain the matching of the angle
+/// brackets in `tts`. It may cover further tt's.
+fn expression_length(tts :&[Tt], angles :&AngleBrackets,
+		strict :bool) -> Result<usize, Error> {
 	let mut expr_len = 0;
 	let mut last_was_punctuation = true;
 	let mut last_was_group = true;
-	'outer: while expr_len < tts.len() {
+	while expr_len < tts.len() {
 		let tt = &tts[tts.len() - 1 - expr_len];
 		let mut is_punctuation = false;
 		let mut is_group = false;
@@ -136,10 +1151,19 @@ fn expression_length(tts :&[Tt]) -> usize {
 		match tt {
 			Tt::Group(group) => {
 				is_group = true;
-				// If the group wasn't terminated by a punctuation,
-				// it belongs to e.g. a function body, if clause, etc,
-				// but not to our expression
-				if !last_was_punctuation {
+				// Calls or indexing like `f(a)(b)` or `m[0][1]`
+				let is_call = last_was_group && group.delimiter() != Delimiter::Brace &&
+					!is_attribute(&tts[..tts.len() - expr_len]);
+				if !last_was_punctuation && !is_call {
+					if strict && last_was_group {
+						// Either a block-like statement followed by an
+						// expression like `match x {} (y)`, or a call
+						// like `Foo {} (y)`.
+						return Err(ambiguous_receiver(tt));
+					}
+					// If the group wasn't terminated by a punctuation,
+					// it belongs to e.g. a function body, if clause, etc,
+					// but not to our expression
 					break;
 				}
 
@@ -148,160 +1172,97 @@ fn expression_length(tts :&[Tt]) -> usize {
 				// If it's delimitered by braces, so is { ... },
 				// we need to check whether the group was an if,
 				// match, else, or else if block, and add stuff accordingly.
-
-				// If we have {}. it might be an if, match or else block.
 				if group.delimiter() == Delimiter::Brace {
-					loop {
-						//println!("GROUP SEARCH IS IN {}", tts[tts.len() - 1 - expr_len]);
-						// We are at the end, it was a {} block.
-						if expr_len + 1 >= tts.len() {
-							break;
-						}
-						let tt_before = &tts[tts.len() - 2 - expr_len];
-						match tt_before {
-							Tt::Group(_group) => {
-								// e.g. `if foo() {}`, `if { true } {}`, `if if {true } else { false } {}`,
-								// `if bools[..] {}`.
-								// Basically, just start the expression search and hope for the best :)
-							},
-							Tt::Ident(id) => {
-								let id_str = id.to_string();
-								if id_str == "else" {
-									expr_len += 3;
-									//println!("ELSE");
-									// Continue the chain search
-									continue;
-								} else {
-									// Any other ident: must be part of an expression like if something.expr {}.foo().
-									// Start the full expression search
-								}
-							},
-							Tt::Punct(p) => match p.as_char() {
-								// These indicate the end of the expression
-								';' | ',' => {
-									expr_len += 1;
-									break 'outer;
-								},
-								// This indicates the group was part of something else,
-								// like a prior macro foo! {} . bar!().
-								// Just continue the outer search normally
-								'!' => break,
-								// Unsupported stuff
-								// TODO support closures
-								'|' => panic!("Closures not supported yet"),
-								p => panic!("Group expr search encountered unsupported punctuation {}", p),
-							},
-							Tt::Literal(_lit) => {
-								// Start the expression search
-							},
-						}
-						// Perform the expression search
-						let sub_expr_len = expression_length(&tts[..tts.len() - 1 - expr_len]);
-						expr_len += sub_expr_len;
-						// Now check what's beyond the expression
-						let tt_before = if tts.len() < 2 + expr_len {
-							None
-						} else {
-							tts.get(tts.len() - 2 - expr_len)
-						};
-						let tt_before_that = if tts.len() < 3 + expr_len {
-							None
-						} else {
-							tts.get(tts.len() - 3 - expr_len)
-						};
-
-						/*println!("group search before: {} {:?} {:?}", sub_expr_len,
-							tt_before_that.map(|v| v.to_string()),
-							tt_before.map(|v| v.to_string()));*/
-						match (tt_before_that, tt_before) {
-							(Some(Tt::Ident(id_t)), Some(Tt::Ident(id))) => {
-								let id_t = id_t.to_string();
-								let id = id.to_string();
-								if id_t == "else" && id == "if" {
-									// Else if clause.
-									expr_len += 3;
-									// Continue the chain search.
-								} else if id == "match" {
-									// Done with the if/match chain search.
-									is_group = false;
-									expr_len += 1;
-									break;
-								}
-							},
-							(_, Some(Tt::Ident(id))) => {
-								let id = id.to_string();
-								if id == "if" || id == "match" {
-									// Done with the if/match chain search.
-									is_group = false;
-									expr_len += 1;
-									break;
-								} else {
-									// IDK something failed
-								}
-							},
-							(_, Some(Tt::Punct(p))) => {
-								match p.as_char() {
-									// This can be either == or if let Foo() =
-									'=' => {
-										if let Some(Tt::Punct(p_t)) = tt_before_that {
-											if p_t.as_char() == '=' {
-												// Parse another expr
-												// TODO
-												// TODO maybe instead of calling expression_length above,
-												// create a new function that calls expression_length internally and
-												// handles this case, calling expression_length again if needed?
-												// Or pass some kind of precedence setting to expression_length?
-												panic!("== in if clause not supported yet");
-											}
-										}
-										panic!("if let not supported");
-									},
-									_ => panic!("{} in if not supported yet", p),
-								}
-							},
-							(None, None) => {
-								// Nothing comes before tt.
-								// We are done
-								break;
-							},
-							_ => {
-								panic!("Hit unsupported case: {:?} {:?}", tt_before_that.map(|v| v.to_string()),
-									tt_before.map(|v| v.to_string()));
-							},
-						}
+					let tts_up_to = &tts[..tts.len() - expr_len];
+					if let Some(len) = braced_expression_length(tts_up_to, angles, strict)? {
+						// Nothing can be before an if/match/etc. expression
+						// that is still part of the postfix macro chain.
+						return Ok(expr_len + len);
 					}
 				}
 			},
-			Tt::Ident(id) => {
+			Tt::Ident(_) => {
 				if !last_was_punctuation && !last_was_group {
+					let is_label = matches!(tts[..tts.len() - 1 - expr_len].last(),
+						Some(Tt::Punct(p)) if p.as_char() == '\'');
+					if strict && ends_operand(tt) && !is_label {
+						// Not a keyword, so it's unknown syntax
+						return Err(ambiguous_receiver(tt));
+					}
 					// two idents following another... must be `if <something>.foo!() { <stuff> }`
 					// or something like it.
 					break;
 				}
 
-				// &mut <something>.foo!() where <something> is punctuation or a group
-				let id_str = id.to_string();
-				if id_str == "mut" {
+				// Keywords like in `&mut <something>.foo!()` or
+				// `return (<something>).foo!()` terminate the
+				// expression, except for the few that can be
+				// part of one, like `self` or `.await`.
+				if !ends_operand(tt) {
 					break;
 				}
+
+				// Labels like in `break 'a <something>.foo!()`
+				if let Some(Tt::Punct(p)) = tts[..tts.len() - 1 - expr_len].last() {
+					if p.as_char() == '\'' {
+						break;
+					}
+				}
 			},
 			Tt::Punct(p) => {
 				is_punctuation = true;
 				match p.as_char() {
-					// No expression termination
-					'.' if p.spacing() == Spacing::Alone => (),
-					':' | '?' => (),
+					// No expression termination,
+					// unless it's the end of a `..`
+					'.' if p.spacing() == Spacing::Alone => {
+						match tts[..tts.len() - 1 - expr_len].last() {
+							Some(Tt::Punct(p)) if p.as_char() == '.' &&
+								p.spacing() == Spacing::Joint => break,
+							_ => (),
+						}
+					},
+					// A `:` that isn't part of a `::` path separator
+					// ends the receiver, like the one after the field
+					// name in `Foo { a : x.foo!() }`, unless it belongs
+					// to the label of a labeled block.
+					':' => {
+						let before = &tts[..tts.len() - 1 - expr_len];
+						let after = tts.get(tts.len() - expr_len);
+						let is_path = (p.spacing() == Spacing::Joint &&
+								matches!(after, Some(Tt::Punct(q)) if q.as_char() == ':')) ||
+							matches!(before.last(), Some(Tt::Punct(q))
+								if q.as_char() == ':' && q.spacing() == Spacing::Joint);
+						let is_label = matches!(before, [.., Tt::Punct(q), Tt::Ident(_)]
+							if q.as_char() == '\'');
+						if !is_path && !is_label {
+							break;
+						}
+					},
+					// `?` can't be followed by an identifier,
+					// so this must be e.g. a `$(..)?` repetition.
+					'?' if !last_was_punctuation && !last_was_group => break,
+					'?' => (),
+					// Metavariables like `$x` or `$crate` paths
+					// inside macro_rules definitions.
+					// Treat them like the identifier.
+					'$' if !last_was_punctuation && !last_was_group => {
+						is_punctuation = false;
+					},
 					// Depending on the context, ! can either be a prefix
 					// operator or belong to a macro invocation.
 					// It can also appear in `!=`.
 					'!' => {
 						if last_was_punctuation {
-							// The ! is part of `!=`.
-							// Right now we panic, because the = should already
-							// terminate the expression, but in the future when
-							// we implement a mode with different precedence
-							// we might want to support this case.
-							panic!("! followed by punctuation");
+							// The ! is part of `!=`, or of some other
+							// punctuation. The = should already have
+							// terminated the expression, so report an
+							// error, but in the future when we implement
+							// a mode with different precedence we might
+							// want to support this case.
+							return Err(Error::new(p.span(),
+								"`!` followed by punctuation is not supported in \
+								postfix macro receivers yet; put the receiver \
+								in parentheses".to_string()));
 						} else {
 							if tts.len() - expr_len - 1 == 0 {
 								// Leading `!` means it's a prefix operator
@@ -309,25 +1270,50 @@ fn expression_length(tts :&[Tt]) -> usize {
 							}
 							let tt_before = &tts[tts.len() - expr_len - 2];
 							if let Tt::Ident(_id) = tt_before {
+								if !ends_operand(tt_before) {
+									// Keyword like in `if !<something>.foo!()`,
+									// so the `!` is a prefix operator
+									break;
+								}
 								// Macro invocation. Continue.
-
-								// TODO if the ident is a keyword like if or
-								// mut, it's obviously NOT a macro invocation
-								// and the ! is a prefix operator after all.
 							} else {
 								// `!` is a prefix operator
 								break;
 							}
 						}
 					},
+					// Generic arguments like in `None::<()>` or
+					// `.collect::<Vec<_>>()` don't terminate the expression.
+					'>' => {
+						let tts_up_to = &tts[..tts.len() - expr_len];
+						if let Some(len) = generic_args_length(tts_up_to, angles) {
+							expr_len += len - 1;
+						} else if starts_with_path_sep(&tts[tts.len() - expr_len..]) {
+							// `>::` can't be a comparison, so this is the
+							// end of a qualified path like `<Vec<u8>>::new`
+							// or `<u8 as Default>::default`, which starts
+							// the expression.
+							return match qualified_path_length(tts_up_to) {
+								Some(len) => Ok(expr_len + len),
+								None => Err(Error::new(p.span(), "unexpected `>` \
+									before `::` in postfix macro receiver; \
+									put the receiver in parentheses".to_string())),
+							};
+						} else {
+							// Comparison operator
+							break;
+						}
+					},
 					// These all terminate expressions
 					'.' if p.spacing() == Spacing::Joint => break,
-					',' | ';' | '+' | '/' | '%' | '=' | '<' | '>' | '|' | '^' => break,
+					',' | ';' | '+' | '/' | '%' | '=' | '<' | '|' | '^' => break,
 					// All of & * and - can be safely prepended to expressions in any number,
 					// however they have weaker precedence than postfix functions.
 					// So they just terminate the expression.
 					'&' | '*' | '-' => break,
-					c => panic!("Encountered unsupported punctuation {}", c),
+					c => return Err(Error::new(p.span(), format!(
+						"unsupported punctuation `{}` in postfix macro receiver; \
+						put the receiver in parentheses", c))),
 				}
 			},
 			Tt::Literal(_lit) => {
@@ -337,32 +1323,805 @@ fn expression_length(tts :&[Tt]) -> usize {
 		last_was_punctuation = is_punctuation;
 		last_was_group = is_group;
 	}
-	expr_len
+	Ok(expr_len)
 }
 
-fn prepend_macro_arg_to_group(tokens :&[Tt], gr :Group) -> Group {
-	// Build the expr's tt.
+/// The error for a receiver whose start
+/// can't be determined from the grammar
+fn ambiguous_receiver(tt :&Tt) -> Error {
+	Error::new(tt.span(), format!("ambiguous postfix macro receiver, \
+		it might or might not include `{}`; put the receiver \
+		in parentheses", tt))
+}
+
+/// Checks whether `tts` ends with the brackets
+/// of an attribute, like `#[a]` or `#![a]`.
+fn is_attribute(tts :&[Tt]) -> bool {
+	let before = match tts.split_last() {
+		Some((Tt::Group(gr), before)) if gr.delimiter() == Delimiter::Bracket => before,
+		_ => return false,
+	};
+	let before = match before.split_last() {
+		Some((Tt::Punct(p), before)) if p.as_char() == '!' => before,
+		_ => before,
+	};
+	matches!(before.last(), Some(Tt::Punct(p)) if p.as_char() == '#')
+}
+
+/// Checks whether `tts` ends with a path in expression
+/// context, like `a::b::<u8>::C`, and returns its length.
+fn path_length(tts :&[Tt], angles :&AngleBrackets) -> usize {
+	let mut len = 0;
+	loop {
+		let mut end = tts.len() - len;
+		// Generic arguments of the segment
+		if let Some(Tt::Punct(p)) = tts[..end].last() {
+			if p.as_char() == '>' {
+				match generic_args_length(&tts[..end], angles) {
+					Some(args_len) => end -= args_len + 2,
+					None => return 0,
+				}
+			}
+		}
+		match tts[..end].last() {
+			Some(tt @ Tt::Ident(_)) if ends_operand(tt) => end -= 1,
+			_ => return 0,
+		}
+		// Metavariables like `$x` or `$crate`
+		if let Some(Tt::Punct(p)) = tts[..end].last() {
+			if p.as_char() == '$' {
+				end -= 1;
+			}
+		}
+		len = tts.len() - end;
+		match &tts[..end] {
+			[.., Tt::Punct(c1), Tt::Punct(c2)] if c1.as_char() == ':' &&
+					c1.spacing() == Spacing::Joint && c2.as_char() == ':' => {
+				len += 2;
+				if end == 2 || !matches!(tts[end - 3], Tt::Ident(_) | Tt::Punct(_)) {
+					// Leading `::`
+					return len;
+				}
+			},
+			_ => return len,
+		}
+	}
+}
+
+/// Checks whether `tts` ends with a generic argument list
+/// of a path in expression context, like `::<Vec<u8>>`,
+/// and returns the length of the list without the `::`.
+///
+/// `tts` must end with a `>`.
+fn generic_args_length(tts :&[Tt], angles :&AngleBrackets) -> Option<usize> {
+	let i = angles.opening(tts.len() - 1)?;
+	// Only `::<` opens a generic argument list
+	// in expression context, otherwise it's a
+	// comparison.
+	match tts[..i] {
+		[.., Tt::Punct(ref c1), Tt::Punct(ref c2)] if c1.as_char() == ':' &&
+			c1.spacing() == Spacing::Joint && c2.as_char() == ':' => {
+			Some(tts.len() - i)
+		},
+		_ => None,
+	}
+}
+
+/// Checks whether `tts` ends with the `<..>` at the
+/// start of a qualified path like `<Vec<u8>>::new`,
+/// and returns the length of it.
+fn qualified_path_length(tts :&[Tt]) -> Option<usize> {
+	let mut depth = 0;
+	for (i, tt) in tts.iter().enumerate().rev() {
+		match tt {
+			Tt::Punct(p) if p.as_char() == '>' => match tts[..i].last() {
+				// The `->` in `Fn() -> u8`
+				Some(Tt::Punct(p)) if p.as_char() == '-' &&
+					p.spacing() == Spacing::Joint => (),
+				_ => depth += 1,
+			},
+			Tt::Punct(p) if p.as_char() == '<' => {
+				depth -= 1;
+				if depth == 0 {
+					return Some(tts.len() - i);
+				}
+			},
+			// These can't appear in types
+			Tt::Punct(p) if matches!(p.as_char(), ';' | '|' | '.') => return None,
+			_ => (),
+		}
+	}
+	None
+}
+
+/// Whether `tts` starts with a `::` path separator
+fn starts_with_path_sep(tts :&[Tt]) -> bool {
+	match tts {
+		[Tt::Punct(c1), Tt::Punct(c2), ..] => c1.as_char() == ':' &&
+			c1.spacing() == Spacing::Joint && c2.as_char() == ':',
+		_ => false,
+	}
+}
+
+/// Determine the length of an expression ending in a
+/// brace delimited group that is followed by a postfix
+/// operator, like `if a == b { .. } else { .. }.foo!()`.
+///
+/// `tts` ends with the group.
+///
+/// Returns `None` if the group is part of a macro
+/// invocation like `foo! { .. }`, and should be
+/// treated like any other group by the caller.
+fn braced_expression_length(tts :&[Tt], angles :&AngleBrackets,
+		strict :bool) -> Result<Option<usize>, Error> {
+	// Number of tt's of the if/else chain after the current group
+	let mut len = 0;
+	loop {
+		if len >= tts.len() {
+			// There is nothing before the `else`
+			let else_tt = &tts[0];
+			return Err(Error::new(else_tt.span(),
+				"expected a block before `else`".to_string()));
+		}
+		let group_pos = tts.len() - 1 - len;
+		match &tts[group_pos] {
+			Tt::Group(gr) if gr.delimiter() == Delimiter::Brace => (),
+			tt => return Err(Error::new(tt.span(),
+				"expected a block before `else`".to_string())),
+		}
+		// A closure with a return type, like `|v| -> u8 { .. }`.
+		// Unlike closures without return type, the postfix
+		// macro applies to the entire closure.
+		if len == 0 {
+			if let Some(closure_len) = closure_head_length(&tts[..group_pos]) {
+				return Ok(Some(closure_len + 1));
+			}
+		}
+		let tt_before = match tts[..group_pos].last() {
+			Some(tt) => tt,
+			None if len == 0 => {
+				// It was a {} block.
+				return Ok(Some(1));
+			},
+			None => {
+				let tt = &tts[group_pos];
+				return Err(Error::new(tt.span(), "expected `if` before the \
+					block followed by `else`".to_string()));
+			},
+		};
+		match tt_before {
+			Tt::Ident(id) if id.to_string() == "else" => {
+				// The group is an else block.
+				// Continue the chain search with the block before it.
+				len += 2;
+				continue;
+			},
+			// This indicates the group was part of something else,
+			// like a prior macro foo! {} . bar!().
+			// Just continue the outer search normally
+			Tt::Punct(p) if p.as_char() == '!' && len == 0 => {
+				return Ok(None);
+			},
+			// A closure without return type, like `|v| { .. }`.
+			// The postfix macro applies to the body only.
+			// Also covers binary or like `a | { .. }`.
+			Tt::Punct(p) if p.as_char() == '|' && len == 0 => {
+				return Ok(Some(1));
+			},
+			Tt::Ident(id) if len == 0 && is_block_keyword(&id.to_string()) => {
+				// Something like `unsafe { .. }` or `async move { .. }`
+				let mut block_len = 2;
+				if id.to_string() == "move" {
+					match tts[..group_pos - 1].last() {
+						Some(Tt::Ident(id)) if id.to_string() == "async" => block_len += 1,
+						_ => return Err(Error::new(id.span(),
+							"expected `async` before `move`".to_string())),
+					}
+				}
+				let start = group_pos + 1 - block_len;
+				return Ok(Some(block_len + label_length(&tts[..start])));
+			},
+			Tt::Punct(p) if len == 0 && p.as_char() == ':' && label_length(&tts[..group_pos]) > 0 => {
+				// Labeled block like `'a: { .. }`
+				return Ok(Some(1 + label_length(&tts[..group_pos])));
+			},
+			tt if len == 0 && !ends_operand(tt) => {
+				let struct_path_len = path_length(&tts[..group_pos], angles);
+				if struct_path_len > 0 {
+					// Struct literal with generic arguments
+					return Ok(Some(struct_path_len + 1));
+				}
+				// Something like `= { .. }` or `; { .. }`.
+				// It was a {} block.
+				return Ok(Some(1));
+			},
+			tt if !ends_operand(tt) => {
+				return Err(Error::new(tt.span(), format!("expected `if` \
+					before the block followed by `else`, found `{}`", tt)));
+			},
+			_ => (),
+		}
+
+		// The group is preceded by an expression, e.g.
+		// `if foo() == bar {}`, `if { true } {}`,
+		// `if if {true } else { false } {}`, or `match bools[..] {}`.
+		// Find the keyword before that expression.
+		// If there is none, the group might belong to
+		// a struct literal, like `Foo { a : 42 }`.
+		let struct_path_len = if len == 0 {
+			path_length(&tts[..group_pos], angles)
+		} else {
+			0
+		};
+		let cond_len = match let_condition_length(&tts[..group_pos], angles, strict) {
+			Ok(cond_len) => cond_len,
+			Err(_) if struct_path_len > 0 && !strict => return Ok(Some(struct_path_len + 1)),
+			Err(e) => return Err(e),
+		};
+		let head_pos = group_pos - cond_len;
+		match tts[..head_pos].last() {
+			Some(Tt::Ident(id)) if id.to_string() == "if" => {
+				len += cond_len + 2;
+				match tts[..head_pos - 1].last() {
+					Some(Tt::Ident(id)) if id.to_string() == "else" => {
+						// Else if clause.
+						// Continue the chain search.
+						len += 1;
+					},
+					_ => {
+						// Done with the if chain search.
+						return Ok(Some(len));
+					},
+				}
+			},
+			Some(Tt::Ident(id)) if id.to_string() == "match" && len == 0 => {
+				// Done with the match search.
+				return Ok(Some(cond_len + 2));
+			},
+			Some(Tt::Ident(id)) if id.to_string() == "while" && len == 0 => {
+				let start = head_pos - 1;
+				return Ok(Some(cond_len + 2 + label_length(&tts[..start])));
+			},
+			Some(Tt::Ident(id)) if id.to_string() == "in" && len == 0 => {
+				// For loop. Find the `for` before the pattern.
+				let start = match for_pattern_length(&tts[..head_pos]) {
+					Some(pat_len) => head_pos - pat_len,
+					None => return Err(Error::new(id.span(),
+						"expected `for` before `in`".to_string())),
+				};
+				return Ok(Some(group_pos + 1 - start + label_length(&tts[..start])));
+			},
+			_ if struct_path_len > 0 => {
+				// Struct literal
+				return Ok(Some(struct_path_len + 1));
+			},
+			Some(tt) => {
+				return Err(Error::new(tt.span(), format!(
+					"couldn't determine where the postfix macro \
+					receiver starts, unexpected `{}` before it; put \
+					the receiver in parentheses", tt)));
+			},
+			None => {
+				let tt = &tts[0];
+				return Err(Error::new(tt.span(),
+					"couldn't determine where the postfix macro \
+					receiver starts; put the receiver in parentheses"
+					.to_string()));
+			},
+		}
+	}
+}
+
+/// Checks whether `tts` ends with a label like `'a:`,
+/// and returns its length if so, or 0 otherwise.
+fn label_length(tts :&[Tt]) -> usize {
+	match tts {
+		[.., Tt::Punct(q), Tt::Ident(_), Tt::Punct(c)] if q.as_char() == '\'' &&
+			q.spacing() == Spacing::Joint && c.as_char() == ':' => 3,
+		_ => 0,
+	}
+}
+
+/// Checks whether `tts` ends with the `for PAT in` part
+/// of a `for` loop, and returns its length if so.
+fn for_pattern_length(tts :&[Tt]) -> Option<usize> {
+	// Skip the `in`
+	for (i, tt) in tts[..tts.len() - 1].iter().enumerate().rev() {
+		match tt {
+			Tt::Ident(id) => {
+				let id = id.to_string();
+				if id == "for" {
+					return Some(tts.len() - i);
+				}
+				if is_keyword(&id) && !ends_operand(tt) &&
+						!matches!(id.as_str(), "mut" | "ref" | "box") {
+					return None;
+				}
+			},
+			Tt::Punct(p) if matches!(p.as_char(), ';' | '=') => return None,
+			_ => (),
+		}
+	}
+	None
+}
+
+/// Checks whether `tts` ends with the head of a closure
+/// with a return type, like `move |v| -> u8`, and
+/// returns its length if so.
+fn closure_head_length(tts :&[Tt]) -> Option<usize> {
+	// Find the `->` before the return type
+	let mut angle_depth = 0;
+	let mut arrow_pos = None;
+	for (i, tt) in tts.iter().enumerate().rev() {
+		match tt {
+			Tt::Punct(p) => match p.as_char() {
+				'>' => match tts[..i].last() {
+					Some(Tt::Punct(p)) if p.as_char() == '-' && p.spacing() == Spacing::Joint => {
+						if angle_depth == 0 {
+							arrow_pos = Some(i - 1);
+							break;
+						}
+					},
+					_ => angle_depth += 1,
+				},
+				'<' => {
+					if angle_depth == 0 {
+						return None;
+					}
+					angle_depth -= 1;
+				},
+				// Punctuation that can appear in types
+				'&' | '*' | ':' | '\'' | '+' | '!' | '_' | '-' => (),
+				',' | '=' if angle_depth > 0 => (),
+				_ => return None,
+			},
+			Tt::Ident(id) => {
+				let id = id.to_string();
+				let type_keyword = matches!(id.as_str(),
+					"dyn" | "impl" | "fn" | "unsafe" | "extern" | "for" |
+					"mut" | "const" | "as");
+				if is_keyword(&id) && !ends_operand(tt) && !type_keyword {
+					return None;
+				}
+			},
+			Tt::Group(_) | Tt::Literal(_) => (),
+		}
+	}
+	let arrow_pos = arrow_pos?;
+
+	// Find the parameter list before the `->`
+	let params_end = match tts[..arrow_pos].last() {
+		Some(Tt::Punct(p)) if p.as_char() == '|' => arrow_pos - 1,
+		_ => return None,
+	};
+	let params_start = tts[..params_end].iter()
+		.rposition(|tt| matches!(tt, Tt::Punct(p) if p.as_char() == '|'))?;
+
+	// Qualifiers before the parameter list
+	let mut start = params_start;
+	for qualifier in &["move", "async", "static"] {
+		match tts[..start].last() {
+			Some(Tt::Ident(id)) if id.to_string() == *qualifier => start -= 1,
+			_ => (),
+		}
+	}
+	Some(tts.len() - start)
+}
+
+/// Binary operators, as they can appear between
+/// the operands of e.g. an `if` condition.
+///
+/// Longer operators need to come before their prefixes.
+const BINARY_OPERATORS :&[&str] = &[
+	"..=", "==", "!=", "<=", ">=", "&&", "||", "<<", ">>", "..",
+	"<", ">", "+", "-", "*", "/", "%", "^", "&", "|",
+];
+
+/// Walk the chain of tt's that form a full expression,
+/// made of operands joined by binary operators, like
+/// the condition of an `if` or the scrutinee of a `match`.
+///
+/// Returns the number of token tree items that
+/// belong to the expression.
+fn condition_length(tts :&[Tt], angles :&AngleBrackets,
+		strict :bool) -> Result<usize, Error> {
+	let mut len = 0;
+	loop {
+		let operand_len = expression_length(&tts[..tts.len() - len], angles, strict)?;
+		if operand_len == 0 {
+			return Ok(len);
+		}
+		len += operand_len;
+
+		// <operand> as <type>, the operand was the type
+		// and the expression being cast comes before it.
+		if let Some(Tt::Ident(id)) = tts[..tts.len() - len].last() {
+			if id.to_string() == "as" {
+				len += 1;
+				continue;
+			}
+		}
+
+		// Walk over the prefix operators of the operand,
+		// and the binary operator before them, if any.
+		loop {
+			let end = tts.len() - len;
+			// &mut <operand>
+			if let [.., Tt::Punct(p), Tt::Ident(id)] = &tts[..end] {
+				if p.as_char() == '&' && id.to_string() == "mut" {
+					len += 1;
+					continue;
+				}
+			}
+			let run_len = punct_run_length(&tts[..end]);
+			if run_len == 0 {
+				return Ok(len);
+			}
+			let start = end - run_len;
+			let run = tts[start..end].iter()
+				.map(|tt| match tt {
+					Tt::Punct(p) => p.as_char(),
+					_ => unreachable!(),
+				})
+				.collect::<String>();
+			let after_operand = start > 0 && ends_operand(&tts[start - 1]);
+			if after_operand {
+				let is_binary = BINARY_OPERATORS.iter().any(|op| {
+					run.starts_with(op) && run[op.len()..].chars().all(is_prefix_operator)
+				});
+				if !is_binary {
+					// Something like `= -a`, include the prefix
+					// operators but nothing before them.
+					len += run.chars().rev()
+						.take(run_len - 1)
+						.take_while(|c| is_prefix_operator(*c))
+						.count();
+					return Ok(len);
+				}
+				len += run_len;
+				// Continue with the next operand
+				break;
+			} else if run.chars().all(is_prefix_operator) {
+				len += run_len;
+			} else {
+				return Ok(len);
+			}
+		}
+	}
+}
+
+/// Walk the chain of tt's that form the condition of
+/// an `if`, which may contain `let` expressions
+/// joined by `&&`, like in `if let Some(v) = foo && v > 0`.
+///
+/// Returns the number of token tree items that
+/// belong to the condition.
+fn let_condition_length(tts :&[Tt], angles :&AngleBrackets,
+		strict :bool) -> Result<usize, Error> {
+	let mut len = 0;
+	loop {
+		len += condition_length(&tts[..tts.len() - len], angles, strict)?;
+		let let_len = match let_pattern_length(&tts[..tts.len() - len]) {
+			Some(let_len) => let_len,
+			None => return Ok(len),
+		};
+		len += let_len;
+		// Let chains
+		let end = tts.len() - len;
+		match &tts[..end] {
+			[.., tt, Tt::Punct(p1), Tt::Punct(p2)] if ends_operand(tt) &&
+					(p1.as_char(), p1.spacing(), p2.as_char()) == ('&', Spacing::Joint, '&') => {
+				len += 2;
+			},
+			_ => return Ok(len),
+		}
+	}
+}
+
+/// Checks whether `tts` ends with the `let PAT =` part
+/// of a `let` expression, and returns its length if so.
+fn let_pattern_length(tts :&[Tt]) -> Option<usize> {
+	// Check for a `=` that isn't part of another operator
+	match tts {
+		[.., Tt::Punct(p), Tt::Punct(eq)] if eq.as_char() == '=' &&
+			p.spacing() == Spacing::Joint => return None,
+		[.., Tt::Punct(eq)] if eq.as_char() == '=' => (),
+		_ => return None,
+	}
+	// Find the let before the pattern. The pattern can't
+	// contain let or most other keywords.
+	for (i, tt) in tts[..tts.len() - 1].iter().enumerate().rev() {
+		match tt {
+			Tt::Ident(id) => {
+				let id = id.to_string();
+				if id == "let" {
+					return Some(tts.len() - i);
+				}
+				if is_keyword(&id) && !ends_operand(tt) &&
+						!matches!(id.as_str(), "mut" | "ref" | "box") {
+					return None;
+				}
+			},
+			Tt::Punct(p) if p.as_char() == ';' => return None,
+			Tt::Punct(p) if p.as_char() == '=' => {
+				// Allow `..=` in range patterns
+				match tts[..i].last() {
+					Some(Tt::Punct(p)) if p.as_char() == '.' &&
+						p.spacing() == Spacing::Joint => (),
+					_ => return None,
+				}
+			},
+			_ => (),
+		}
+	}
+	None
+}
+
+/// Returns the number of punctuation characters at
+/// the end of `tts` that are joined together.
+fn punct_run_length(tts :&[Tt]) -> usize {
+	let mut len = 0;
+	for tt in tts.iter().rev() {
+		match tt {
+			Tt::Punct(p) if p.as_char() != '?' => {
+				if len > 0 && p.spacing() != Spacing::Joint {
+					break;
+				}
+				len += 1;
+			},
+			_ => break,
+		}
+	}
+	len
+}
+
+fn is_prefix_operator(c :char) -> bool {
+	matches!(c, '&' | '*' | '-' | '!')
+}
+
+/// Whether the token tree can be the last one of an operand
+fn ends_operand(tt :&Tt) -> bool {
+	match tt {
+		Tt::Group(_) | Tt::Literal(_) => true,
+		Tt::Ident(id) => {
+			let id = id.to_string();
+			// Keywords that can still end an operand
+			let operand_keyword = matches!(id.as_str(),
+				"self" | "Self" | "super" | "crate" | "true" | "false" | "await");
+			operand_keyword || !is_keyword(&id)
+		},
+		Tt::Punct(p) => p.as_char() == '?',
+	}
+}
+
+/// Keywords that precede a block to form an expression,
+/// like `unsafe { .. }`.
+fn is_block_keyword(id :&str) -> bool {
+	matches!(id, "unsafe" | "loop" | "async" | "move" | "const" | "try")
+}
+
+/// Whether the identifier is a keyword
+///
+/// This includes strict and reserved keywords of all editions,
+/// but not weak keywords like `union`, which can be used
+/// as identifiers.
+fn is_keyword(id :&str) -> bool {
+	matches!(id,
+		"as" | "break" | "const" | "continue" | "crate" | "else" | "enum" |
+		"extern" | "false" | "fn" | "for" | "if" | "impl" | "in" | "let" |
+		"loop" | "match" | "mod" | "move" | "mut" | "pub" | "ref" | "return" |
+		"self" | "Self" | "static" | "struct" | "super" | "trait" | "true" |
+		"type" | "unsafe" | "use" | "where" | "while" | "async" | "await" |
+		"dyn" | "abstract" | "become" | "box" | "do" | "final" | "macro" |
+		"override" | "priv" | "typeof" | "unsized" | "virtual" | "yield" |
+		"try")
+}
+
+#[cfg(feature = "full-parse")]
+fn tokens_to_string(tts :&[Tt]) -> String {
+	tts.iter().cloned().collect::<TokenStream>().to_string()
+}
+
+/// The span of the receiver, for the tokens that
+/// get added to pass it to the macro
+/// Replaces the postfix macro invocation whose `.`, path and `!`
+/// are at the end of the visited tokens of `frame` with the
+/// `compile_error!` invocation of `error`.
+///
+/// The receiver, as far as the heuristic can guess it, is
+/// replaced as well, so that the code around it still parses,
+/// and the compiler goes on to report further errors.
+fn fail_invocation(frame :&mut Frame, mac_len :usize, error :Error) {
+	let dot_pos = frame.res.len() - mac_len - 1;
+	let receiver_len = match expression_length(&frame.res[..dot_pos], &frame.angles, false) {
+		Ok(len) => len,
+		// Tokens the heuristic doesn't support, like
+		// in `inner!(@tag x.foo!())`. Guess the last tt.
+		Err(_) => dot_pos.min(1),
+	};
+	let start = dot_pos - receiver_len;
+	frame.res.truncate(start);
+	frame.angles.truncate(start);
+	frame.last_invocation = None;
+	frame.res.extend(error.to_compile_error());
+}
+
+fn receiver_span(tokens :&[Tt]) -> Span {
+	// There is no stable way to join spans, so the
+	// first token stands in for the entire receiver.
+	expansion_span(tokens[0].span())
+}
+
+/// Returns a span at the location of `span`, that
+/// resolves at the call site like the tokens of any other
+/// macro expansion. Lints like `unused_braces` don't fire
+/// for delimiters with such spans.
+fn expansion_span(span :Span) -> Span {
+	Span::call_site().located_at(span)
+}
+
+/// Copies a group that makes up the entire receiver.
+///
+/// Its delimiters are only needed to delimit the receiver
+/// in postfix position, and might be unnecessary after the
+/// rewrite, like in `(a + b).dbg!()`. So they get the span
+/// of tokens created by the expansion.
+fn receiver_group(gr :&Group) -> Group {
+	if gr.stream().is_empty() {
+		// Not delimiters, but the unit value `()`
+		// or an empty array or block
+		return gr.clone();
+	}
+	let mut res = Group::new(gr.delimiter(), gr.stream());
+	res.set_span(expansion_span(gr.span()));
+	res
+}
+
+fn spanned(mut tt :Tt, span :Span) -> Tt {
+	tt.set_span(span);
+	tt
+}
+
+/// Builds the tt to pass the receiver to the macro with
+///
+/// If a group is created for it, it gets the given span.
+fn wrap_receiver(tokens :&[Tt], wrap :Delimiter, span :Span) -> Tt {
 	// If there is only one token and it's
 	// a variable/constant/static name, or a literal,
 	// we pass it directly, otherwise we wrap it in {}
-	// to make it safer.
-	let expr = match &tokens {
-		&[tt] if matches!(tt, Tt::Literal(_) | Tt::Ident(_)) => {
+	// to make it safer, or in () if configured, to keep
+	// place expressions like `a.b[0]` intact.
+	match tokens {
+		[tt] if matches!(tt, Tt::Literal(_) | Tt::Ident(_)) => {
 			tt.clone()
 		},
+		[Tt::Group(g)] if wrap == Delimiter::Parenthesis &&
+				g.delimiter() == Delimiter::Parenthesis => {
+			Tt::Group(receiver_group(g))
+		},
 		_ => {
-			let expr_stream = tokens.iter().cloned().collect();
-			let expr_gr = Group::new(Delimiter::Brace, expr_stream);
+			let expr_stream = match tokens {
+				[Tt::Group(g)] => Tt::Group(receiver_group(g)).into(),
+				_ => tokens.iter().cloned().collect(),
+			};
+			let mut expr_gr = Group::new(wrap, expr_stream);
+			expr_gr.set_span(span);
 			Tt::Group(expr_gr)
 		},
-	};
+	}
+}
 
+/// Passes the receiver to the macro, by replacing the
+/// `$self` placeholder arguments and the `..` spread marker,
+/// or, if there are none, by prepending it to the arguments.
+///
+/// `elements` are the elements of tuple receivers,
+/// that the spread marker gets replaced with.
+/// If `placeholder` is false, `$self` arguments
+/// are kept as they are.
+fn prepend_macro_arg_to_group(expr :Tt, elements :Option<Vec<Vec<Tt>>>,
+		placeholder :bool, gr :Group) -> Group {
 	let stream = gr.stream();
 	let delim = gr.delimiter();
-	let mut res_stream = TokenStream::from(expr);
-	if !stream.is_empty() {
-		res_stream.extend(std::iter::once(Tt::Punct(Punct::new(',', Spacing::Alone))));
-		res_stream.extend(stream);
+	let mut found = false;
+	let mut args = Vec::new();
+	for arg in split_args(stream.clone()) {
+		match &elements {
+			_ if placeholder && is_placeholder(&arg) => {
+				args.push(vec![expr.clone()]);
+				found = true;
+			},
+			Some(elements) if is_spread_marker(&arg) => {
+				args.extend(elements.iter().cloned());
+				found = true;
+			},
+			_ => args.push(arg),
+		}
+	}
+	let res_stream = if found {
+		join_args(args, expr.span())
+	} else {
+		let span = expr.span();
+		let mut res_stream = TokenStream::from(expr);
+		if !stream.is_empty() {
+			let comma = Tt::Punct(Punct::new(',', Spacing::Alone));
+			res_stream.extend(std::iter::once(spanned(comma, span)));
+			res_stream.extend(stream);
+		}
+		res_stream
+	};
+	let mut res = Group::new(delim, res_stream);
+	res.set_span(gr.span());
+	res
+}
+
+/// Splits a stream at the top level commas
+///
+/// A trailing comma results in an empty last argument.
+fn split_args(stream :TokenStream) -> Vec<Vec<Tt>> {
+	let mut args = vec![Vec::new()];
+	for tt in stream {
+		match tt {
+			Tt::Punct(p) if p.as_char() == ',' => args.push(Vec::new()),
+			tt => args.last_mut().unwrap().push(tt),
+		}
+	}
+	if args.len() == 1 && args[0].is_empty() {
+		args.clear();
+	}
+	args
+}
+
+/// Joins arguments with commas, the inverse of `split_args`
+///
+/// The commas get the given span.
+fn join_args(args :Vec<Vec<Tt>>, span :Span) -> TokenStream {
+	let mut res = Vec::new();
+	for (i, arg) in args.into_iter().enumerate() {
+		if i > 0 {
+			res.push(spanned(Tt::Punct(Punct::new(',', Spacing::Alone)), span));
+		}
+		res.extend(arg);
+	}
+	res.into_iter().collect()
+}
+
+/// Whether the group is a tuple like `(a, b)`, `(a,)` or `()`,
+/// as opposed to a parenthesized expression like `(a + b)`
+fn is_tuple(gr :&Group) -> bool {
+	gr.delimiter() == Delimiter::Parenthesis &&
+		(gr.stream().is_empty() || split_args(gr.stream()).len() > 1)
+}
+
+/// Returns the elements of the tuple `(a, b)`,
+/// and whether there is a trailing comma.
+fn tuple_elements(tuple :&Group) -> (Vec<Vec<Tt>>, bool) {
+	let mut elements = split_args(tuple.stream());
+	let trailing_comma = matches!(elements.last(), Some(el) if el.is_empty());
+	if trailing_comma {
+		elements.pop();
+	}
+	(elements, trailing_comma)
+}
+
+/// Whether the argument is the `$self` placeholder for the receiver
+///
+/// Unlike e.g. `_`, it can't be a meaningful argument on its own,
+/// except inside `macro_rules!` definitions, where it can be a
+/// metavariable. There, it isn't treated as placeholder.
+fn is_placeholder(arg :&[Tt]) -> bool {
+	match arg {
+		[Tt::Punct(p), Tt::Ident(id)] => p.as_char() == '$' && id.to_string() == "self",
+		_ => false,
+	}
+}
+
+/// Whether the argument is the `..` marker for
+/// spreading the elements of a tuple receiver
+fn is_spread_marker(arg :&[Tt]) -> bool {
+	match arg {
+		[Tt::Punct(p1), Tt::Punct(p2)] => p1.as_char() == '.' &&
+			p1.spacing() == Spacing::Joint && p2.as_char() == '.',
+		_ => false,
 	}
-	Group::new(delim, res_stream)
 }
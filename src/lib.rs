@@ -2,7 +2,7 @@
 Postfix macros on stable Rust, today.
 
 ```
-# use postfix_macros::{postfix_macros, unwrap_or};
+use postfix_macros::prelude::*;
 # #[derive(Debug, Clone, Copy)] enum Custom { Enum(()), EnumOther}
 # let val = [((),Custom::EnumOther,)];
 postfix_macros! {
@@ -28,8 +28,15 @@ for your greatest convenience.
 | **`match`** with default case | [`match_or!`] |
 | **`if`** `<bool>`, `bool::then` | [`then!`] |
 | **`else`** | [`then_else!`] |
+
+The [`verbatim!`] macro marks tokens that should
+not be rewritten by [`postfix_macros!`].
+
+All of these macros can be imported at once through the [`prelude`],
+so that using another one of them doesn't require changing imports.
 */
 #![forbid(unsafe_code)]
+#![allow(clippy::tabs_in_doc_comments)]
 
 /**
 Proc macro to parse code containing postfix macros,
@@ -39,12 +46,87 @@ The main macro of this crate.
 
 The macro scans for `expr.macro_invoc!(params)` patterns
 and changes them to `macro_invoc!(expr, params)` patterns.
+Macros can also be invoked by path, like in
+`expr.path::to::macro_invoc!(params)`.
 
 If no parameters are passed to the postfix macro,
 then no trailing `,` is emitted.
+
+To pass the receiver somewhere else than as the first
+parameter, use `$self` as a placeholder parameter, like in
+`value.println!("got {}", $self)`. All parameters consisting
+of just `$self` get replaced by the receiver.
+Inside `macro_rules!` definitions, where `$self` can be
+a metavariable, they are kept as they are.
+
+If the receiver is a tuple, a `..` parameter gets replaced by
+the elements of the tuple, so that `(left, right).assert_eq!(..)`
+becomes `assert_eq!(left, right)`. Parenthesized expressions
+like `(a + b)` are not tuples, and get passed as a whole.
+
+Receivers consisting of more than one token are wrapped in
+a `{}` block. To preserve place expressions like `a.b[0]`,
+put a `#![postfix(wrap = "paren")]` attribute at the top
+of the macro input or of a block inside it, to use
+parentheses instead.
+
+Receivers are evaluated as often as the macro mentions them.
+With a `#![postfix(eval_once)]` attribute, the receivers
+are bound to a variable, so that they are only evaluated once.
+Single variable names and literals aren't bound, while other
+place expressions like `a.b` get moved into the variable.
+
+The macros that may be used as postfix macros can be restricted
+through `#![postfix(only(dbg, unwrap_or))]` and
+`#![postfix(deny(dbg))]`. Invocations of other macros are left
+untouched, or reported as errors with `#![postfix(strict)]`.
+Strict mode also rejects receivers whose start the grammar
+doesn't make clear, like `(x)` in `match x {} (x).dbg!()`,
+instead of guessing it.
+
+Macros like [`then_else!`] that continue the previous
+invocation of a chain can be listed with
+`#![postfix(chain(otherwise))]`. If their receiver is
+a postfix macro invocation, like in
+`value.attempt!{..}.otherwise!{..}`, they get it passed
+in a structured form instead, with its path, receiver
+and parameters:
+`otherwise!(@postfix_chain [attempt] (value) (..), ..)`.
+
+Settings can be combined, like in
+`#![postfix(wrap = "paren", eval_once, only(dbg, unwrap_or))]`.
 */
 pub use postfix_macros_impl::postfix_macros;
 
+/**
+Rewrites the postfix macros of an entire file
+
+Reads the file at the given path, relative to the directory
+of the crate's `Cargo.toml`, and expands to its contents,
+rewritten like [`postfix_macros!`] would. This way, whole
+modules can be written in postfix style, without wrapping
+them in a macro invocation, which keeps the usual formatting
+and indentation working.
+
+```ignore
+mod parser {
+	postfix_macros::postfix_include!("src/parser.rs");
+}
+```
+
+The macro can be used where items or statements are
+expected, so the file may contain `#![postfix(..)]` settings
+at its top, but no other inner attributes or doc comments.
+As there is no stable way to create spans that point into
+the file, errors in its contents point at the invocation.
+The crate is rebuilt when the file changes.
+*/
+pub use postfix_macros_impl::postfix_include;
+
+#[cfg(feature = "full-parse")]
+#[doc(hidden)]
+pub use postfix_macros_impl::__postfix_macros_differential;
+
 /**
 Either unwraps the content passed to the macro,
 or executes the passed code block.
@@ -197,9 +279,21 @@ for i in 1..10 {
 assert_eq!(w, 72);
 # }
 ```
+
+Inside [`postfix_macros!`], the macro gets the
+[`then!`] invocation before it passed as a chain,
+as described in the [`postfix_macros!`] docs.
 */
 #[macro_export]
 macro_rules! then_else {
+	(@postfix_chain [$(::)? $(postfix_macros::)? then] ($cond:expr) ($($if_body:tt)*),
+			$($body:tt)*) => {
+		if $cond {
+			$($if_body)*
+		} else {
+			$($body)*
+		}
+	};
 	({ then! ( $cond:expr, $($if_body:tt)* ) }, $($body:tt)*) => {
 		if $cond {
 			$($if_body)*
@@ -215,3 +309,50 @@ macro_rules! then_else {
 		}
 	};
 }
+
+/**
+Marks tokens that [`postfix_macros!`] should leave exactly as written
+
+Inside [`postfix_macros!`], no postfix macro invocations
+in the tokens passed to the marker are rewritten. The marker
+itself is kept, and expands to the tokens passed to it,
+just like outside of [`postfix_macros!`].
+
+The arguments of `stringify!`, `concat!`, `quote!` and
+`quote_spanned!` are never rewritten. Further macros
+that take raw tokens, like in code generation, can be added
+to that list via the
+`#![postfix(verbatim(my_macro, path::to::other))]` attribute.
+
+```
+use postfix_macros::{postfix_macros, verbatim};
+macro_rules! tokens {
+	($($t:tt)*) => { stringify!($($t)*) };
+}
+postfix_macros! {
+	let s = verbatim!(tokens!(x.foo!()));
+	assert_eq!(s.replace(' ', ""), "x.foo!()");
+	(verbatim!(1 + 2) * 3).assert_eq!(9);
+}
+```
+*/
+#[macro_export]
+macro_rules! verbatim {
+	($($t:tt)*) => { $($t)* };
+}
+
+/**
+All macros of this crate
+
+```
+use postfix_macros::prelude::*;
+postfix_macros! {
+	let v = Some(4).unwrap_or!{ 0 };
+	(v > 3).then!{ "big" }.then_else!{ "small" }.assert_eq!("big");
+}
+```
+*/
+pub mod prelude {
+	pub use crate::{postfix_macros, postfix_include,
+		unwrap_or, match_or, then, then_else, verbatim};
+}
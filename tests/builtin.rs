@@ -1,4 +1,7 @@
-use postfix_macros::{match_or, postfix_macros, then, then_else, unwrap_or};
+use postfix_macros::prelude::*;
+// Check that the heuristic agrees with the full parser
+#[cfg(feature = "full-parse")]
+use postfix_macros::__postfix_macros_differential as postfix_macros;
 
 postfix_macros! {
     #[test]
@@ -61,5 +64,11 @@ postfix_macros! {
         false.then!(check_then = true).then_else!(check_else = true);
         assert!(!check_then);
         assert!(check_else);
+
+        let check = 1 > 2;
+        let v = check
+            .postfix_macros::then!{ "then" }
+            .postfix_macros::then_else!{ "else" };
+        assert_eq!(v, "else");
     }
 }
@@ -0,0 +1,234 @@
+//! Tests for the `#![postfix(..)]` configuration attributes
+
+#[cfg(not(feature = "full-parse"))]
+use postfix_macros::postfix_macros;
+// Check that the heuristic agrees with the full parser
+#[cfg(feature = "full-parse")]
+use postfix_macros::__postfix_macros_differential as postfix_macros;
+use postfix_macros::verbatim;
+
+macro_rules! take {
+	($v:expr) => { std::mem::take(&mut $v) };
+}
+
+macro_rules! stringify_recv {
+	($v:tt) => { stringify!($v) };
+}
+
+struct Names {
+	items :Vec<String>,
+}
+
+postfix_macros! {
+	#![postfix(wrap = "paren")]
+	#[test]
+	fn wrap_paren() {
+		let mut names = Names { items : vec!["hi".to_string()] };
+		names.items[0].take!().assert_eq!("hi");
+		names.items[0].assert_eq!("");
+		let ptr = names.items.std::ptr::addr_of!();
+		assert!(std::ptr::eq(ptr, &names.items));
+
+		// Single tokens are passed as-is
+		names.stringify_recv!().assert_eq!("names");
+		names.items.len().stringify_recv!().assert_eq!("(names.items.len())");
+		(names.items.len()).stringify_recv!().assert_eq!("(names.items.len())");
+	}
+}
+
+postfix_macros! {
+	#[test]
+	fn wrap_brace() {
+		let items = [1];
+		items.len().stringify_recv!().assert_eq!("{ items.len() }");
+		(items.len()).stringify_recv!().assert_eq!("{ (items.len()) }");
+		items[0].assert_eq!(1);
+	}
+}
+
+postfix_macros! {
+	#[test]
+	fn wrap_in_block() {
+		let mut items = [1, 2];
+		{
+			#![postfix(wrap = "paren")]
+			items[0].take!().assert_eq!(1);
+		}
+		items.len().stringify_recv!().assert_eq!("{ items.len() }");
+		items[0].assert_eq!(0);
+	}
+}
+
+macro_rules! twice {
+	($v:expr) => { ($v, $v) };
+}
+
+macro_rules! add {
+	($v:expr, $w:expr) => { $v + $w };
+}
+
+postfix_macros! {
+	#![postfix(eval_once)]
+	#[test]
+	fn eval_once() {
+		let mut it = 0..;
+		it.next().twice!().assert_eq!((Some(0), Some(0)));
+		it.next().unwrap().add!(it.next().unwrap().add!(1)).assert_eq!(1 + 2 + 1);
+		let v = it.next().unwrap().twice!().0;
+		v.assert_eq!(3);
+
+		// Temporaries live until the end of the invocation
+		String::from("hi").as_str().twice!().assert_eq!(("hi", "hi"));
+
+		// The binding is hygienic
+		let __postfix_receiver = 10;
+		it.next().unwrap().add!(__postfix_receiver).assert_eq!(14);
+
+		// Variables are passed as they are, without moving them
+		let v = vec![1];
+		v.assert_eq!(vec![1]);
+		v.len().assert_eq!(1);
+	}
+}
+
+postfix_macros! {
+	#![postfix(eval_once)]
+	#[test]
+	fn eval_once_spread() {
+		let mut it = 0..;
+		(it.next().unwrap(), it.next().unwrap()).add!(..).assert_eq!(1);
+		(it.next(), it.next()).format!("{:?} {:?} {:?}", $self, ..)
+			.assert_eq!("(Some(2), Some(3)) Some(2) Some(3)");
+		(it.next(),).twice!(..).assert_eq!((Some(4), Some(4)));
+		(it.next().unwrap(),).add!(.., 1).assert_eq!(6);
+	}
+}
+
+/// Removes the whitespace, which depends on the compiler version
+fn squash(s :&str) -> String {
+	s.replace(' ', "")
+}
+
+/// Like `stringify!`, but the arguments get rewritten
+macro_rules! tokens {
+	($($t:tt)*) => { stringify!($($t)*) };
+}
+
+postfix_macros! {
+	#![postfix(only(assert_eq, add, std::format))]
+	#[test]
+	fn only() {
+		1.add!(2).assert_eq!(3);
+		"{}".std::format!(1).assert_eq!("1");
+		// Other macros are left untouched
+		squash(tokens!(x.twice!())).assert_eq!("x.twice!()");
+		squash(tokens!(x.std::stringify!())).assert_eq!("x.std::stringify!()");
+		{
+			// Nested lists narrow down the outer one
+			#![postfix(only(add, twice))]
+			assert_eq!(1.add!(2).add!(3), 6);
+			assert_eq!(squash(tokens!(x.twice!() + x.assert_eq!())), "x.twice!()+x.assert_eq!()");
+		}
+		squash(tokens!(x.add!())).assert_eq!("add!(x)");
+	}
+}
+
+postfix_macros! {
+	#![postfix(deny(twice, std::format))]
+	#[test]
+	fn deny() {
+		1.add!(2).assert_eq!(3);
+		"{}".format!(1).assert_eq!("1");
+		squash(tokens!(x.twice!())).assert_eq!("x.twice!()");
+		squash(tokens!(x.std::format!())).assert_eq!("x.std::format!()");
+		{
+			#![postfix(deny(add))]
+			squash(tokens!(x.twice!() + x.add!())).assert_eq!("x.twice!()+x.add!()");
+		}
+		squash(tokens!(x.add!())).assert_eq!("add!(x)");
+	}
+}
+
+postfix_macros! {
+	#[test]
+	fn verbatim() {
+		squash(verbatim!{ tokens!(x.twice!()) }).assert_eq!("x.twice!()");
+		squash(tokens!(verbatim!(x.twice!()) + x.twice!()))
+			.assert_eq!("verbatim!(x.twice!())+twice!(x)");
+		squash(postfix_macros::verbatim!{ tokens!(x.twice!()) }).assert_eq!("x.twice!()");
+		// The marked tokens stay together
+		(verbatim!(1 + 2) * 3).assert_eq!(9);
+		squash(verbatim!(1 + 2).stringify!()).assert_eq!("{verbatim!(1+2)}");
+		// Arguments of stringify! are left untouched
+		squash(stringify!(x.twice!())).assert_eq!("x.twice!()");
+		squash(std::stringify!(x.twice!())).assert_eq!("x.twice!()");
+		// Verbatim macros can still be used as postfix macros
+		squash(1.add!(2).stringify!()).assert_eq!("{add!(1,2)}");
+		squash(x.stringify!(a.b!())).assert_eq!("x,a.b!()");
+		{
+			#![postfix(verbatim(tokens))]
+			squash(tokens!(x.twice!())).assert_eq!("x.twice!()");
+		}
+	}
+}
+
+postfix_macros! {
+	#![postfix(strict)]
+	#[test]
+	fn strict() {
+		let names = Names { items : vec![] };
+		Names { items : vec![] }.items.len().assert_eq!(0);
+		// The field name isn't part of the receiver
+		let filled = Names { items : vec![String::new()].take!() };
+		filled.items.len().assert_eq!(1);
+		match names.items.len() { 0 => 1, _ => 2 }.assert_eq!(1);
+		let nested = [[1, 2]];
+		nested[0][1].assert_eq!(2);
+		let mut i = 0;
+		'a: loop {
+			i += 1;
+			if i == 2 {
+				break 'a nested[0][i - 1];
+			}
+		}.assert_eq!(2);
+		// Qualified paths start the receiver
+		<Vec<u8>>::new().len().assert_eq!(0);
+		<u8 as Default>::default().assert_eq!(0);
+	}
+}
+
+macro_rules! attempt {
+	($v:expr, $f:expr) => { $v.map($f).ok() };
+}
+
+macro_rules! otherwise {
+	(@postfix_chain [attempt] ($v:expr) ($f:expr), $default:expr) => {
+		$v.map($f).unwrap_or($default)
+	};
+	(@postfix_chain [$($path:tt)*] ($v:expr) ($($args:tt)*)) => {
+		($v, stringify!($($path)*), stringify!($($args)*))
+	};
+	($v:expr) => { $v };
+}
+
+postfix_macros! {
+	#![postfix(chain(otherwise))]
+	#[test]
+	fn chain() {
+		"4".parse::<u8>().attempt!(|v| v * 2).assert_eq!(Some(8));
+		"4".parse::<u8>().attempt!(|v| v * 2).otherwise!(0).assert_eq!(8);
+		"x".parse::<u8>().attempt!(|v| v * 2).otherwise!(0).assert_eq!(0);
+		// Structured access to path, receiver and parameters
+		let (v, path, args) = 1.add!(2).otherwise!();
+		(v, path, squash(args)).assert_eq!((1, "add", "2".to_string()));
+		let (v, path, _) = 1.std::format!().otherwise!();
+		(v, squash(path)).assert_eq!((1, "std::format".to_string()));
+		// Continuations of continuations
+		let (v, path, _) = 1.add!(2).otherwise!().otherwise!();
+		(v, path).assert_eq!((3, "otherwise"));
+		// Receivers that are no postfix invocation
+		"4".parse::<u8>().otherwise!().assert_eq!(Ok(4));
+		1.add!(2).min(2).otherwise!().assert_eq!(2);
+		add!(1, 2).otherwise!().assert_eq!(3);
+	}
+}
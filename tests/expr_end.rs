@@ -1,7 +1,22 @@
 //! Tests for when the expression ends
-#![allow(unused_parens, unused_braces, unused_must_use)]
+// The tests are about the shape of the receivers,
+// not about writing idiomatic code
+#![allow(unused_must_use, clippy::absurd_extreme_comparisons,
+	clippy::bool_comparison, clippy::deref_addrof, clippy::erasing_op,
+	clippy::identity_op, clippy::if_same_then_else,
+	clippy::let_underscore_future, clippy::let_unit_value,
+	clippy::manual_unwrap_or, clippy::manual_unwrap_or_default,
+	clippy::match_single_binding, clippy::needless_bool, clippy::needless_ifs,
+	clippy::needless_return, clippy::never_loop, clippy::no_effect,
+	clippy::nonminimal_bool, clippy::op_ref, clippy::overly_complex_bool_expr,
+	clippy::redundant_pattern_matching, clippy::short_circuit_statement,
+	clippy::unnecessary_literal_unwrap)]
 
+#[cfg(not(feature = "full-parse"))]
 use postfix_macros::postfix_macros;
+// Check that the heuristic agrees with the full parser
+#[cfg(feature = "full-parse")]
+use postfix_macros::__postfix_macros_differential as postfix_macros;
 
 macro_rules! stringify_eq {
 	($v:tt, $w:ident) => {{
@@ -13,11 +28,19 @@ macro_rules! stringify_eq {
 		$v
 	}};
 	($v:tt, $($w:tt)*) => {{
-		assert_eq!(stringify!($v), stringify!({$($w)*}));
+		assert_eq!(stringify!($v), stringify!({ $($w)* }));
 		$v
 	}};
 }
 
+// Like stringify_eq, but doesn't evaluate the expression,
+// for syntax that isn't available in the edition of the tests
+macro_rules! stringify_eq_unevaluated {
+	($v:tt, $($w:tt)*) => {{
+		assert_eq!(stringify!($v), stringify!({ $($w)* }));
+	}};
+}
+
 postfix_macros! {
 	#[test]
 	fn prefix_operator_ends_expr() {
@@ -47,14 +70,66 @@ postfix_macros! {
 	}
 }
 
+// Test that call and index chains belong to the receiver
+postfix_macros! {
+	#[test]
+	fn call_chains() {
+		let arr = ["hello", "world"];
+		let nested = [arr];
+		nested[0][1].stringify_eq!(nested[0][1]);
+		let f = |i :usize| move |j :usize| nested[i][j];
+		f(0)(1).stringify_eq!(f(0)(1));
+		f(0)(1).len().stringify_eq!(f(0)(1).len());
+		[f][0](0)(1).stringify_eq!([f][0](0)(1));
+		// The brackets of an attribute are not indexed
+		#[rustfmt::skip]
+		(arr).len().stringify_eq!((arr).len());
+	}
+}
+
 // Tests for colons
 postfix_macros! {
 	#[test]
 	fn colon() {
 		let _ :String = Default::default().stringify_eq!(Default::default());
 		std::iter::once(()).stringify_eq!(std::iter::once(()));
-		// TODO this doesn't work
-		//None::<()>.stringify_eq!(None::<()>);
+	}
+	#[test]
+	fn turbofish() {
+		None::<()>.stringify_eq!(None::<()>);
+		Vec::<u8>::new().stringify_eq!(Vec::<u8>::new());
+		"42".parse::<u64>().stringify_eq!("42".parse::<u64>());
+		let _ = [1, 2].iter().collect::<Vec<_>>()
+			.stringify_eq!([1, 2].iter().collect::<Vec<_>>());
+		let _ = [1, 2].iter().map(|x| vec![*x]).collect::<Vec<Vec<_>>>().len()
+			.stringify_eq!([1, 2].iter().map(|x| vec![*x]).collect::<Vec<Vec<_>>>().len());
+		None::<Box<dyn Fn() -> u8>>.stringify_eq!(None::<Box<dyn Fn() -> u8>>);
+		None::<Box<dyn Iterator<Item = u8>>>.stringify_eq!(None::<Box<dyn Iterator<Item = u8>>>);
+		None::<Box<dyn Fn() + Send>>.stringify_eq!(None::<Box<dyn Fn() + Send>>);
+		Some(1).map::<u8, fn(u8) -> u8>(|x| x + 1)
+			.stringify_eq!(Some(1).map::<u8, fn(u8) -> u8>(|x| x + 1));
+		Result::<u8, ()>::Ok(1).stringify_eq!(Result::<u8, ()>::Ok(1));
+		let _ = (0, Result::<u8, ()>::Ok(1).stringify_eq!(Result::<u8, ()>::Ok(1)));
+		std::mem::size_of::<(u8, u16)>().stringify_eq!(std::mem::size_of::<(u8, u16)>());
+	}
+	#[test]
+	fn qualified_path() {
+		<Vec<u8>>::new().stringify_eq!(<Vec<u8>>::new());
+		<u8 as Default>::default().stringify_eq!(<u8 as Default>::default());
+		let _ = 1 + <u8 as Default>::default().stringify_eq!(<u8 as Default>::default());
+		<Vec<Box<dyn Fn(u8) -> u8>> as Default>::default().len()
+			.stringify_eq!(<Vec<Box<dyn Fn(u8) -> u8>> as Default>::default().len());
+	}
+	#[test]
+	fn range_ends_expr() {
+		let _ = 0..3.stringify_eq!(3);
+		let _ = 0..=3.stringify_eq!(3);
+	}
+	#[test]
+	fn comparison_ends_expr() {
+		let _ = 1 < 2.stringify_eq!(2);
+		let _ = 1 > 2.stringify_eq!(2);
+		let _ = (1 > 0) == ((0 < 1) > (2 > 1)).stringify_eq!(((0 < 1) > (2 > 1)));
 	}
 }
 
@@ -170,7 +245,6 @@ postfix_macros! {
 	}
 }
 
-/*
 // Test that things which belong into an if expr
 // but terminate a chained . expr work correcly.
 postfix_macros! {
@@ -179,17 +253,285 @@ postfix_macros! {
 		// == terminates a . chain, but fits inside an if
 		if 42 == (10*4) { "hi" } else { "hello" }
 			.to_string()
-			.stringify_eq!(if 42 == (10*4) { "hi" } else { "hello" });
+			.stringify_eq!(if 42 == (10*4) { "hi" } else { "hello" }.to_string());
 	}
 	#[test]
 	fn if_eq_mul_belongs() {
 		// * terminates a . chain, but fits inside an if
 		if 42 == 10*4 { "hi" } else { "hello" }
 			.to_string()
-			.stringify_eq!(if 42 == 10*4 { "hi" } else { "hello" });
+			.stringify_eq!(if 42 == 10*4 { "hi" } else { "hello" }.to_string());
+	}
+	#[test]
+	fn if_comparisons_belong() {
+		let (a, b) = (1, 2);
+		if a != b { 1 } else { 2 }.stringify_eq!(if a != b { 1 } else { 2 });
+		if a < b { 1 } else { 2 }.stringify_eq!(if a < b { 1 } else { 2 });
+		if a > b { 1 } else { 2 }.stringify_eq!(if a > b { 1 } else { 2 });
+		if a <= b { 1 } else { 2 }.stringify_eq!(if a <= b { 1 } else { 2 });
+		if a >= -b { 1 } else { 2 }.stringify_eq!(if a >= -b { 1 } else { 2 });
+		let (c, d) = (1u8, 2u8);
+		if c as u16 == 3 { 1 } else { 2 }.stringify_eq!(if c as u16 == 3 { 1 } else { 2 });
+		if c == -(d as i8) as u8 { 1 } else { 2 }.stringify_eq!(if c == -(d as i8) as u8 { 1 } else { 2 });
+	}
+	#[test]
+	fn if_logic_ops_belong() {
+		let (a, b) = (true, false);
+		if a && b { 1 } else { 2 }.stringify_eq!(if a && b { 1 } else { 2 });
+		if a || b && a { 1 } else { 2 }.stringify_eq!(if a || b && a { 1 } else { 2 });
+		if a == b || *&a { 1 } else { 2 }.stringify_eq!(if a == b || *&a { 1 } else { 2 });
+	}
+	#[test]
+	fn else_if_eq_belongs() {
+		let (a, b) = (1, 2);
+		if a == b { 1 } else if a.max(3) == b + 1 { 2 } else { 3 }
+			.stringify_eq!(if a == b { 1 } else if a.max(3) == b + 1 { 2 } else { 3 });
+	}
+	#[test]
+	fn match_eq_belongs() {
+		let (a, b) = (1, 2);
+		match a == b { _ => "hi" }.stringify_eq!(match a == b { _ => "hi" });
+	}
+}
+
+// Test that if let expressions belong
+postfix_macros! {
+	#[test]
+	fn if_let_belongs() {
+		let v = Some(3);
+		if let Some(w) = v { w } else { 0 }
+			.stringify_eq!(if let Some(w) = v { w } else { 0 });
+		if let Some(1..=5) | None = v.map(|w| w + 1) { 1 } else { 0 }
+			.stringify_eq!(if let Some(1..=5) | None = v.map(|w| w + 1) { 1 } else { 0 });
+		let _ = if let Some(ref w) = v { *w } else { 0 }
+			.stringify_eq!(if let Some(ref w) = v { *w } else { 0 });
+	}
+	#[test]
+	fn else_if_let_belongs() {
+		let v = Some(3);
+		if v.is_none() { 0 } else if let Some(w) = v { w } else { 1 }
+			.stringify_eq!(if v.is_none() { 0 } else if let Some(w) = v { w } else { 1 });
+		if let None = v { 0 } else if let -3 = -v.unwrap() { 1 } else { 2 }
+			.stringify_eq!(if let None = v { 0 } else if let -3 = -v.unwrap() { 1 } else { 2 });
+	}
+	#[test]
+	fn let_chain_belongs() {
+		if let Some(w) = v && w > 0 { w } else { 0 }
+			.stringify_eq_unevaluated!(if let Some(w) = v && w > 0 { w } else { 0 });
+		if w > 0 && let Some(w) = v && let Ok(u) = w { u } else { 0 }
+			.stringify_eq_unevaluated!(if w > 0 && let Some(w) = v && let Ok(u) = w { u } else { 0 });
+	}
+}
+
+// Test which part of closures belongs to the expression
+postfix_macros! {
+	#[test]
+	fn closure_body_belongs() {
+		let f = |x :u8| { x + 1 }.stringify_eq!({ x + 1 });
+		assert_eq!(f(1), 2);
+		let f = move || { 42 }.stringify_eq!({ 42 });
+		assert_eq!(f(), 42);
+	}
+	#[test]
+	fn closure_with_return_type_belongs() {
+		let f = |x :u8| -> u8 { x + 1 }.stringify_eq!(|x :u8| -> u8 { x + 1 });
+		assert_eq!(f(1), 2);
+		let f = move || -> Option<Vec<u8>> { None }
+			.stringify_eq!(move || -> Option<Vec<u8>> { None });
+		assert_eq!(f(), None);
+		let _ = async move |x :u8| -> u8 { x }
+			.stringify_eq_unevaluated!(async move |x :u8| -> u8 { x });
+	}
+	#[test]
+	fn closure_in_condition() {
+		let items = [1, 2, 3];
+		if items.iter().any(|x| *x > 2) { "a" } else { "b" }
+			.stringify_eq!(if items.iter().any(|x| *x > 2) { "a" } else { "b" });
+		if let Some(x) = items.iter().find(|x| { **x == 2 }) { *x } else { 0 }
+			.stringify_eq!(if let Some(x) = items.iter().find(|x| { **x == 2 }) { *x } else { 0 });
+	}
+}
+
+// Test that struct literals belong
+postfix_macros! {
+	#[derive(Debug, Default, PartialEq)]
+	struct Point {
+		x :u8,
+		y :u8,
+	}
+	#[derive(Debug, PartialEq)]
+	enum Shape {
+		Circle { r :u8 },
+	}
+	mod shapes {
+		pub struct Square<T> {
+			pub a :T,
+		}
+	}
+	#[test]
+	fn struct_literal_belongs() {
+		let p = Point { x : 1, y : 2 }.stringify_eq!(Point { x : 1, y : 2 });
+		assert_eq!(p, Point { x : 1, y : 2 });
+		Point { x : 1, ..Default::default() }
+			.stringify_eq!(Point { x : 1, ..Default::default() });
+		(0, Point { ..p }.stringify_eq!(Point { ..p }));
+	}
+	#[test]
+	fn path_struct_literal_belongs() {
+		let _ = Shape::Circle { r : 1 }.stringify_eq!(Shape::Circle { r : 1 });
+		let _ = self::shapes::Square { a : 1 }.stringify_eq!(self::shapes::Square { a : 1 });
+		let s = shapes::Square::<u8> { a : 1 }.stringify_eq!(shapes::Square::<u8> { a : 1 });
+		assert_eq!(s.a, 1);
+	}
+	#[test]
+	fn struct_literal_with_method_belongs() {
+		let _ = Point { x : 1, y : 2 }.x.stringify_eq!(Point { x : 1, y : 2 }.x);
+		if true { 1 } else { Point { x : 1, y : 2 }.x.stringify_eq!(Point { x : 1, y : 2 }.x) };
+	}
+}
+
+// Test that block-like expressions belong
+postfix_macros! {
+	#[test]
+	#[allow(unused_unsafe)]
+	fn unsafe_block_belongs() {
+		let _ = unsafe { 42 }.stringify_eq!(unsafe { 42 });
+	}
+	#[test]
+	fn loop_belongs() {
+		let _ = loop { break 5 }.stringify_eq!(loop { break 5 });
+		let _ = 'a: loop { break 'a 5 }.stringify_eq!('a: loop { break 'a 5 });
+	}
+	#[test]
+	fn labeled_block_belongs() {
+		let _ = 'a: { break 'a 1 }.stringify_eq!('a: { break 'a 1 });
+		(0, 'a: { break 'a 1 }.stringify_eq!('a: { break 'a 1 }));
+	}
+	#[test]
+	fn async_block_belongs() {
+		let v = 42;
+		let _ = async { 42 }.stringify_eq!(async { 42 });
+		let _ = async move { v }.stringify_eq!(async move { v });
+	}
+	#[test]
+	fn const_block_belongs() {
+		let _ = const { 1 + 1 }.stringify_eq!(const { 1 + 1 });
+	}
+	#[test]
+	fn while_belongs() {
+		let mut i = 0;
+		while i < 3 { i += 1 }.stringify_eq!(while i < 3 { i += 1 });
+		'a: while i < 6 { i += 1; continue 'a }
+			.stringify_eq!('a: while i < 6 { i += 1; continue 'a });
+		let mut v = Some(1);
+		while let Some(_) = v { v = None }.stringify_eq!(while let Some(_) = v { v = None });
+	}
+	#[test]
+	fn for_belongs() {
+		let mut s = 0;
+		for i in 0..3 { s += i }.stringify_eq!(for i in 0..3 { s += i });
+		let arr = [1];
+		'a: for (i, _) in arr.iter().enumerate() { s += i; continue 'a }
+			.stringify_eq!('a: for (i, _) in arr.iter().enumerate() { s += i; continue 'a });
+		for &mut i in &mut [1] { s += i }.stringify_eq!(for &mut i in &mut [1] { s += i });
+		assert_eq!(s, 4);
+	}
+}
+
+// Test that keywords terminate the expression
+postfix_macros! {
+	#[test]
+	fn return_ends_expr() {
+		fn f() -> u8 {
+			return 42.stringify_eq!(42);
+		}
+		fn g() -> u8 {
+			return (42).stringify_eq!((42));
+		}
+		assert_eq!(f() + g(), 84);
+	}
+	#[test]
+	fn break_ends_expr() {
+		let v = loop { break (42).stringify_eq!((42)); };
+		let w = 'outer: loop {
+			loop {
+				break 'outer (v).stringify_eq!((v));
+			}
+		};
+		let x = 'outer: loop {
+			break 'outer w.stringify_eq!(w);
+		};
+		assert_eq!(x, 42);
+	}
+	#[test]
+	fn yield_ends_expr() {
+		// yield stays outside of the invocation,
+		// so it can only be checked unevaluated
+		stringify_eq_unevaluated!({ || yield (1).stringify_eq_unevaluated!((1)) },
+			|| yield stringify_eq_unevaluated!({ (1) }, (1)));
+		stringify_eq_unevaluated!({ || yield 1.stringify_eq_unevaluated!(1) },
+			|| yield stringify_eq_unevaluated!(1, 1));
+	}
+	#[test]
+	fn as_ends_expr() {
+		let x = 300u16;
+		let _ = x as u8 + (1).stringify_eq!((1));
+		let _ = x as u8 == 44.stringify_eq!(44);
+		let _ = x as u8 & (x as u8).stringify_eq!((x as u8));
+		(x as u8).stringify_eq!((x as u8));
+	}
+	#[test]
+	fn keyword_prefix_operator() {
+		let flag = false;
+		if !flag.stringify_eq!(flag) {}
+		if !(flag).stringify_eq!((flag)) {}
+		let mut i = 0;
+		while !(i == 3).stringify_eq!((i == 3)) {
+			i += 1;
+		}
+		match !(flag).stringify_eq!((flag)) { _ => () }
+		let _ = || return !(flag).stringify_eq!((flag));
+	}
+	#[test]
+	fn keyword_before_group_ends_expr() {
+		if (true).stringify_eq!((true)) {}
+		match (1).stringify_eq!((1)) { _ => () }
+		for _ in [1, 2].stringify_eq!([1, 2]) {}
+		let mut v = Some(1);
+		while let Some(_) = (v).stringify_eq!((v)) {
+			v = None;
+		}
+		let w = [1];
+		let _ = &mut [1].stringify_eq!([1]);
+		for _ in &mut (w).stringify_eq!((w)) {}
+	}
+	#[test]
+	fn for_in_group_belongs() {
+		let mut s = 0;
+		for i in [1, 2].iter() { s += i }.stringify_eq!(for i in [1, 2].iter() { s += i });
+		assert_eq!(s, 3);
+	}
+	#[test]
+	fn await_belongs() {
+		let _ = async {
+			let fut = std::future::ready(1);
+			fut.await.stringify_eq!(fut.await)
+		};
+	}
+	#[test]
+	fn self_belongs() {
+		struct Foo(u8);
+		impl Foo {
+			fn get(&self) -> u8 {
+				self.0.stringify_eq!(self.0)
+			}
+			fn new() -> Self {
+				Self(42).stringify_eq!(Self(42))
+			}
+		}
+		assert_eq!(Foo::new().get(), 42);
 	}
 }
-*/
 
 // Postfix macros inside if clauses
 postfix_macros! {
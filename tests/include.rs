@@ -0,0 +1,30 @@
+//! Tests for `postfix_include!`
+
+use postfix_macros::{postfix_include, postfix_macros};
+
+mod items {
+	postfix_macros::postfix_include!("tests/include/items.rs");
+}
+
+#[test]
+fn include_items() {
+	assert_eq!(items::first_or_zero(&[4, 5]), 4);
+	assert_eq!(items::first_or_zero(&[]), 0);
+	assert_eq!(items::describe(&[]), "empty");
+	assert_eq!(items::describe(&[1]), "numbers");
+	assert_eq!(items::next_twice(&mut [3, 4].iter()), (3, 3));
+}
+
+#[test]
+fn include_statements() {
+	postfix_include!("tests/include/statements.rs");
+	postfix_macros! {
+		value.assert_eq!(3);
+	}
+}
+
+// The file ends in an expression without `;`
+#[test]
+fn include_tail_expression() {
+	postfix_include!("tests/include/tail.rs");
+}
@@ -0,0 +1,21 @@
+#![postfix(eval_once)]
+
+use postfix_macros::prelude::*;
+
+macro_rules! twice {
+	($v:expr) => { ($v, $v) };
+}
+
+pub fn first_or_zero(v :&[u32]) -> u32 {
+	v.first().copied().unwrap_or!{ return 0 }
+}
+
+pub fn describe(v :&[u32]) -> &'static str {
+	v.is_empty()
+		.then!{ "empty" }
+		.then_else!{ "numbers" }
+}
+
+pub fn next_twice(v :&mut std::slice::Iter<'_, u32>) -> (u32, u32) {
+	v.next().copied().unwrap_or!{ 0 }.twice!()
+}
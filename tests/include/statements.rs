@@ -0,0 +1,5 @@
+macro_rules! add {
+	($v:expr, $w:expr) => { $v + $w };
+}
+
+let value = [1, 2].len().add!(1);
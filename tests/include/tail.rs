@@ -0,0 +1,2 @@
+let value = [1, 2, 3].len();
+value.assert_eq!(3)
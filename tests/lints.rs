@@ -0,0 +1,75 @@
+//! Tests that the rewritten code doesn't trigger lints
+#![deny(warnings, clippy::all, clippy::pedantic)]
+
+use postfix_macros::prelude::*;
+#[cfg(feature = "full-parse")]
+use postfix_macros::__postfix_macros_differential as postfix_macros;
+
+fn numbers() -> Vec<u32> {
+	vec![1, 2, 3]
+}
+
+postfix_macros! {
+	#[test]
+	fn std_macros() {
+		let v = numbers();
+		v.len().assert_eq!(3);
+		(v.len() + 1).assert_eq!(4);
+		{ v[0] }.assert_eq!(1);
+		v[1].dbg!().assert_ne!(v[0]);
+		let s = v.len().format!("{}", $self);
+		s.as_str().assert_eq!("3");
+		if v.first().matches!(Some(1)) {
+			(v.len() * 2).println!("{}", $self);
+		}
+		match (v[0] + v[1]).max(v[2]).dbg!() {
+			3 => (),
+			_ => unreachable!(),
+		}
+		(v[0], v[1]).assert_ne!(..);
+	}
+}
+
+postfix_macros! {
+	#[test]
+	fn bundled_macros() {
+		let v = numbers();
+		let first = v.first().copied().unwrap_or!{ return };
+		(first == 1).then!{ first.assert_eq!(1) };
+		(v.len() > 2)
+			.then!{ v.len().assert_eq!(3) }
+			.then_else!{ unreachable!() };
+		if (v.len() > 3).then!{ 1 }.then_else!{ 2 } == 2 {
+			v.len().assert_eq!(3);
+		}
+		let n = v.get(1).match_or!{ Some(n) => *n; 0 };
+		n.assert_eq!(2);
+		let n = (v[0] + v[1]).match_or!{ 3 => 3; 0 };
+		n.assert_eq!(3);
+		v.get(7).copied().unwrap_or!{ 0 }.assert_eq!(0);
+	}
+}
+
+postfix_macros! {
+	#![postfix(eval_once)]
+	#[test]
+	fn eval_once() {
+		let v = numbers();
+		(v[0] + v[1]).assert_eq!(3);
+		(v[0], v[1]).assert_ne!(..);
+		v.iter().sum::<u32>().assert_eq!(6);
+		(v.len() > 2).then!{ v.len().assert_eq!(3) };
+	}
+}
+
+postfix_macros! {
+	#![postfix(wrap = "paren")]
+	#[test]
+	fn wrap_paren() {
+		let v = numbers();
+		(v[0] + v[1]).assert_eq!(3);
+		let first = v[0].std::ptr::addr_of!();
+		std::ptr::eq(first, v.as_ptr()).assert!();
+		(v.len() > 2).then!{ v.len().assert_eq!(3) };
+	}
+}
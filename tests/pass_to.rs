@@ -13,9 +13,13 @@ The macro is a good test for postfix_macros
 anyways.
 */
 
-#![allow(unused_parens)]
+#![allow(clippy::manual_is_multiple_of)]
 
+#[cfg(not(feature = "full-parse"))]
 use postfix_macros::postfix_macros;
+// Check that the heuristic agrees with the full parser
+#[cfg(feature = "full-parse")]
+use postfix_macros::__postfix_macros_differential as postfix_macros;
 
 postfix_macros! {
 	fn is_prime(v: &u32) -> bool {
@@ -1,6 +1,11 @@
-#![allow(unused_parens)]
+#![allow(unused_must_use, clippy::no_effect,
+	clippy::redundant_pattern_matching)]
 
+#[cfg(not(feature = "full-parse"))]
 use postfix_macros::postfix_macros;
+// Check that the heuristic agrees with the full parser
+#[cfg(feature = "full-parse")]
+use postfix_macros::__postfix_macros_differential as postfix_macros;
 
 postfix_macros! {
 	#[test]
@@ -88,3 +93,171 @@ postfix_macros! {
 		((), ().no_comma_pattern_macro!());
 	}
 }
+
+mod helpers {
+	macro_rules! double {
+		($v:expr) => {
+			$v * 2
+		};
+	}
+	pub(crate) use double;
+}
+
+#[macro_export]
+macro_rules! triple {
+	($v:expr) => {
+		$v * 3
+	};
+}
+
+// Macros invoked by path
+postfix_macros! {
+	macro_rules! quadruple {
+		($v:expr) => {
+			$v.$crate::helpers::double!().$crate::helpers::double!()
+		};
+	}
+	#[test]
+	fn path_macros() {
+		21.helpers::double!().assert_eq!(42);
+		14.crate::triple!().assert_eq!(42);
+		14.self::triple!().assert_eq!(42);
+		7.helpers::double!().::std::assert_eq!(14);
+		3.quadruple!().assert_eq!(12);
+	}
+	#[test]
+	fn range_then_macro() {
+		let r = 0..line!();
+		assert!(r.start < r.end);
+	}
+}
+
+// Metavariables of macro_rules definitions as receivers
+postfix_macros! {
+	macro_rules! count_some {
+		($($e:expr),*) => {{
+			let mut count = 0;
+			$(if $e.matches!(Some(_)) { count += 1; })*
+			count
+		}};
+	}
+	macro_rules! sum {
+		($($e:expr),*) => {
+			0 $(+ $e.helpers::double!())*
+		};
+	}
+	macro_rules! first_or {
+		($v:expr, $($alt:tt)*) => {
+			match $v.first() { Some(v) => *v, None => { $($alt)* } }.helpers::double!()
+		};
+	}
+	macro_rules! double_plus {
+		($v:expr $(, $w:expr)?) => {{
+			#[allow(unused_mut)]
+			let mut r = 0;
+			$(r += $w;)? $v.helpers::double!() + r
+		}};
+	}
+	#[test]
+	fn metavariables() {
+		double_plus!(2).assert_eq!(4);
+		double_plus!(2, 1).assert_eq!(5);
+		count_some!(Some(1), None::<u8>, Some(3)).assert_eq!(2);
+		sum!(1, 2, 3).assert_eq!(12);
+		first_or!([4], 0).assert_eq!(8);
+		first_or!([], 5).assert_eq!(10);
+	}
+}
+
+// Long chains of postfix macros. Each of them
+// nests the previous ones one level deeper.
+postfix_macros! {
+	macro_rules! add_one {
+		($v:expr) => { $v + 1 };
+	}
+	#[test]
+	fn long_chain() {
+		0.add_one!().add_one!().add_one!().add_one!().add_one!()
+			.add_one!().add_one!().add_one!().add_one!().add_one!()
+			.add_one!().add_one!().add_one!().add_one!().add_one!()
+			.add_one!().add_one!().add_one!().add_one!().add_one!()
+			.add_one!().add_one!().add_one!().add_one!().add_one!()
+			.add_one!().add_one!().add_one!().add_one!().add_one!()
+			.assert_eq!(30);
+	}
+	#[test]
+	fn previous_invocation_inside_receiver() {
+		let v = 1;
+		match v.add_one!() { 2 => 10, _ => 20 }.add_one!().assert_eq!(11);
+		if v.add_one!() == 2 { 10 } else { 20 }.add_one!().assert_eq!(11);
+		(v.add_one!(), 5).0.add_one!().add_one!().assert_eq!(4);
+		let w = (v.add_one!(), -v.add_one!().add_one!());
+		w.assert_eq!((2, -3));
+	}
+}
+
+// The receiver replaces `$self` placeholder arguments
+postfix_macros! {
+	macro_rules! sub {
+		($v:expr, $w:expr) => { $v - $w };
+	}
+	#[test]
+	fn placeholder() {
+		use std::fmt::Write;
+		let mut out = String::new();
+		42.write!(out, "{}", $self).unwrap();
+		out.assert_eq!("42");
+		"hi".format!("{}, {}!", $self, $self).assert_eq!("hi, hi!");
+		(2 + 2).assert_eq!(4, $self);
+		1.sub!(3, $self).assert_eq!(2);
+		1.sub!(3).assert_eq!(-2);
+		// Only arguments consisting of just `$self` are placeholders,
+		// so `_` keeps its meaning as a pattern
+		assert!(Some(1).matches!(Some(_)));
+		assert!(1.matches!(_));
+	}
+}
+
+// Inside macro_rules definitions, `$self` is a metavariable
+postfix_macros! {
+	macro_rules! assert_is {
+		($self:ident, $v:expr) => { $v.assert_eq!($self) };
+	}
+	macro_rules! write_into {
+		($v:expr, $self:ident) => { $v.write!("{}", $self).unwrap() };
+	}
+	#[test]
+	fn placeholder_metavariable() {
+		use std::fmt::Write;
+		let v = 4;
+		assert_is!(v, 2 + 2);
+		let mut out = String::new();
+		let s = "hi";
+		write_into!(&mut out, s);
+		out.assert_eq!("hi");
+	}
+}
+
+// Tuple receivers are spread by `..`
+postfix_macros! {
+	macro_rules! count {
+		($($v:expr),*) => { 0 $(+ { let _ = $v; 1 })* };
+	}
+	#[test]
+	fn spread() {
+		(2 + 2, 4).assert_eq!(..);
+		(1, 3).sub!(..).assert_eq!(-2);
+		(3,).sub!(4, ..).assert_eq!(1);
+		(1, 3).std::assert_ne!(..);
+		(1,).count!(..).assert_eq!(1);
+		().count!(..).assert_eq!(0);
+		(1, 2, 3,).count!(0, .., 4).assert_eq!(5);
+		// Placeholders get the entire tuple
+		(1, 2).format!("{:?} {} {}", $self, ..).assert_eq!("(1, 2) 1 2");
+		// Receivers other than tuples are not spread,
+		// `..` is passed on as an argument instead
+		[1, 2].count!(..).assert_eq!(2);
+		(1 + 2).count!(..).assert_eq!(2);
+		(3).count!(..).assert_eq!(2);
+	}
+}
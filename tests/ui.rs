@@ -0,0 +1,9 @@
+//! Tests for the errors reported by `postfix_macros!`
+
+// The full parser finds receivers that the heuristic reports errors for
+#[cfg(not(feature = "full-parse"))]
+#[test]
+fn ui() {
+	let t = trybuild::TestCases::new();
+	t.compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,7 @@
+use postfix_macros::postfix_macros;
+
+postfix_macros! {
+	fn main() {
+		let _x = .dbg!();
+	}
+}
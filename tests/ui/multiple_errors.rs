@@ -0,0 +1,11 @@
+use postfix_macros::postfix_macros;
+
+// All errors are reported, including the
+// ones after the rewriting errors
+postfix_macros! {
+	fn main() {
+		let _a = .dbg!();
+		let _b :u8 = 1 + .dbg!();
+		let _c :u8 = "not a number";
+	}
+}
@@ -0,0 +1,14 @@
+use postfix_macros::postfix_macros;
+
+macro_rules! double {
+	($v:expr) => { $v * 2 };
+}
+
+// The type error points at the receiver,
+// not at the whole `postfix_macros!` invocation
+postfix_macros! {
+	fn main() {
+		let names = vec![String::new()];
+		let _d = names[0].clone().double!();
+	}
+}
@@ -0,0 +1,10 @@
+use postfix_macros::postfix_macros;
+
+postfix_macros! {
+	#![postfix(strict, deny(dbg))]
+	fn main() {
+		let x = true;
+		let _y :u8 = 42.dbg!();
+		x.assert!();
+	}
+}
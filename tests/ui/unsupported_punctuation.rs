@@ -0,0 +1,12 @@
+use postfix_macros::postfix_macros;
+
+/// Passes on the expression after the `@`
+macro_rules! tagged {
+	(@ $e:expr) => { $e };
+}
+
+postfix_macros! {
+	fn main() {
+		let _x :u8 = tagged!(@ 2.dbg!());
+	}
+}